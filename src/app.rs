@@ -1,18 +1,43 @@
 use crate::cache::Cache;
-use crate::commands::parse_cmds;
+use crate::colors::Downsample;
+use crate::commands::build_cmd_args;
 use crate::commands::read_config_file;
+use crate::commands::Argument;
 use crate::commands::Command;
-use crate::config::Config;
+use crate::commands::Condition;
+use crate::commands::{complete, find_cmd, parse_script, Script, COMMANDS};
+use crate::commands::{eval_shell_output, parse_cmds};
+use crate::commands::{SetMode, ShellMode};
+use crate::config::{Config, OptInfo};
+use crate::config_layers::{self, TrustState};
+use crate::file_ops::{self, FileOpError};
 use crate::file_tree::{FileTree, FileTreeState};
+use crate::hooks::{HookEvent, Hooks};
+use crate::jobs::{JobDone, Jobs};
 use crate::keymap::KeyMap;
+use crate::mime;
+use crate::open_rules::OpenRules;
+use crate::popup::{Popup, PopupLine};
+use crate::prompt::MsgLevel;
 use crate::prompt::Prompt;
 use crate::prompt::StatusLine;
+use crate::style_rules::{self, StyleRules};
+use crate::util::signal;
+use crate::util::term;
+use crate::util::{self, glob_matches, shell_quote};
 use termion::event::MouseButton;
 use tui::backend::Backend;
 
+use path_absolutize::Absolutize;
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use termion::event::{Key, MouseEvent};
 use tui::layout::{Constraint, Direction, Layout};
+use tui::style::Style;
 use tui::Frame;
 
 pub struct App<'a> {
@@ -21,6 +46,28 @@ pub struct App<'a> {
   pub exit: bool,
   pub statusline: StatusLine<'a>,
   pub keymap: KeyMap,
+  pub popup: Option<Popup>,
+  pub registers: HashMap<String, String>,
+  pub jobs: Jobs,
+  /// Set when the screen has to be redrawn from scratch, i.e. after another
+  /// process used the terminal
+  pub needs_clear: bool,
+  /// Files currently being sourced, innermost last
+  sourcing: Vec<PathBuf>,
+  /// A project config that was found, but not loaded since it is not trusted
+  untrusted_config: Option<PathBuf>,
+  hooks: Hooks,
+  open_rules: OpenRules,
+  /// Parameter of the event whose hooks are running
+  hook_param: Option<String>,
+  /// Root and selection when they were last checked, to trigger hooks when
+  /// they change
+  last_root: PathBuf,
+  last_selection: PathBuf,
+  /// The user config and root passed to `load_config`, to reload the config
+  config_source: Option<(PathBuf, PathBuf)>,
+  /// Config files that were run, with their modification time at the time
+  config_files: Vec<(PathBuf, Option<SystemTime>)>,
 }
 
 impl<'a> App<'a> {
@@ -31,6 +78,19 @@ impl<'a> App<'a> {
       exit: false,
       statusline: StatusLine::new(),
       keymap: KeyMap::new(),
+      popup: None,
+      registers: HashMap::new(),
+      jobs: Jobs::new(),
+      needs_clear: false,
+      sourcing: vec![],
+      untrusted_config: None,
+      hooks: Hooks::default(),
+      open_rules: OpenRules::default(),
+      hook_param: None,
+      last_root: PathBuf::new(),
+      last_selection: PathBuf::new(),
+      config_source: None,
+      config_files: vec![],
     };
     res.read_cache(cache);
     res.tree.update(&res.config);
@@ -46,7 +106,11 @@ impl<'a> App<'a> {
       .split(f.size());
 
     f.render_stateful_widget(FileTree::new(&self.config), chunks[0], &mut self.tree);
-    self.statusline.draw(f, chunks[1]);
+    if let Some(popup) = &mut self.popup {
+      popup.draw(f, chunks[0], self.config.style.highlight);
+    }
+    self.statusline.draw(f, chunks[1], &self.config);
+    f.render_widget(Downsample(self.config.color_depth()), f.size());
   }
 
   pub fn read_cache(&mut self, cache: Cache) {
@@ -56,10 +120,10 @@ impl<'a> App<'a> {
   }
 
   pub fn get_cache(&self) -> Cache {
-    Cache {
-      expanded_paths: self.tree.expanded_paths.clone(),
-      selected_path: self.tree.entry().path.clone(),
-    }
+    Cache::new(
+      self.tree.entry().path.clone(),
+      self.tree.expanded_paths.clone(),
+    )
   }
 
   pub fn update(&mut self) {
@@ -67,13 +131,65 @@ impl<'a> App<'a> {
   }
 
   pub fn tick(&mut self) {
+    self.statusline.info.tick(self.config.message_timeout());
+    self
+      .statusline
+      .info
+      .set_log_size(self.config.message_log_size);
+    if signal::take_reload_request() || (self.config.watch_config && self.config_changed()) {
+      self.run_command(&Command::ReloadConfig);
+    }
     self.update();
+    self.check_tree_changes();
+  }
+
+  /// Run the `Startup` hooks, once the config is loaded and the tree shown
+  pub fn startup(&mut self) {
+    self.last_root = self.tree.root_entry.path.clone();
+    self.last_selection = self.tree.entry().path.clone();
+    self.fire_hook(HookEvent::Startup, "");
+  }
+
+  /// Run the `RootChanged` and `SelectionChanged` hooks if the root or the
+  /// selection changed since the last check
+  fn check_tree_changes(&mut self) {
+    let root = self.tree.root_entry.path.clone();
+    if root != self.last_root {
+      self.last_root = root.clone();
+      self.fire_hook(HookEvent::RootChanged, &root.to_string_lossy());
+    }
+    let selection = self.tree.entry().path.clone();
+    if selection != self.last_selection {
+      self.last_selection = selection.clone();
+      self.fire_hook(HookEvent::SelectionChanged, &selection.to_string_lossy());
+    }
+  }
+
+  /// Run the hooks matching an event, with `param` as `%val{hook_param}`
+  pub fn fire_hook(&mut self, event: HookEvent, param: &str) {
+    let cmds = self.hooks.matching(event, param);
+    if cmds.is_empty() {
+      return;
+    }
+    self.hooks.set_running(event, true);
+    let outer = self.hook_param.replace(param.to_string());
+    self.run_commands(&cmds);
+    self.hook_param = outer;
+    self.hooks.set_running(event, false);
   }
 
   pub fn on_mouse(&mut self, me: MouseEvent) -> Option<()> {
     if self.statusline.has_focus() {
       return Some(());
     }
+    if let Some(popup) = &mut self.popup {
+      match me {
+        MouseEvent::Press(MouseButton::WheelDown, _, _) => popup.on_key(Key::Down),
+        MouseEvent::Press(MouseButton::WheelUp, _, _) => popup.on_key(Key::Up),
+        _ => (false, None),
+      };
+      return Some(());
+    }
     if let MouseEvent::Press(button, _x, y) = me {
       match button {
         MouseButton::Left | MouseButton::Right => {
@@ -83,7 +199,10 @@ impl<'a> App<'a> {
             if entry.is_dir {
               self.tree.toggle_expanded(&entry.path);
             } else {
-              self.run_command(&Command::Open(None))
+              self.run_command(&Command::Open {
+                path: None,
+                with: None,
+              })
             }
           } else {
             self.tree.select_nth(line);
@@ -112,6 +231,16 @@ impl<'a> App<'a> {
       }
       return Some(());
     }
+    if let Some(popup) = &mut self.popup {
+      let (close, cmd) = popup.on_key(k);
+      if close {
+        self.popup = None;
+      }
+      if let Some(cmd) = cmd {
+        self.run_command(&cmd);
+      }
+      return Some(());
+    }
     if let Some(cmd) = self.keymap.get_mapping(k) {
       self.run_command(&cmd);
       return Some(());
@@ -131,7 +260,10 @@ impl<'a> App<'a> {
         if entry.is_dir {
           self.tree.toggle_expanded(&entry.path);
         } else {
-          self.run_command(&Command::Open(None))
+          self.run_command(&Command::Open {
+            path: None,
+            with: None,
+          })
         }
       }
       Key::Char('l') | Key::Right => {
@@ -153,54 +285,159 @@ impl<'a> App<'a> {
         }
       }
       Key::Char('!') => {
-        self.statusline.prompt(Box::new(ShellPrompt {}));
+        self.statusline.prompt(Box::new(ShellPrompt {
+          mode: ShellMode::Silent,
+        }));
+      }
+      Key::Alt('!') => {
+        self.statusline.prompt(Box::new(ShellPrompt {
+          mode: ShellMode::Show,
+        }));
+      }
+      Key::Char('$') => {
+        self.statusline.prompt(Box::new(ShellPrompt {
+          mode: ShellMode::Interactive,
+        }));
+      }
+      Key::Ctrl('z') => {
+        self.run_command(&Command::Suspend);
       }
       Key::Char(':') => {
-        self.statusline.prompt(Box::new(CmdPrompt {}));
+        let options = self.config.options();
+        self.statusline.prompt(Box::new(CmdPrompt { options }));
       }
       Key::Alt('l') => {
         self.run_command(&Command::Cd(None));
       }
-      Key::Char('.') => {
-        self.config.show_hidden = !self.config.show_hidden;
-      }
       _ => {}
     }
     Some(())
   }
 
+  /// Run commands, reporting errors and continuing with the next command
   pub fn run_commands(&mut self, cmds: &Vec<Command>) {
     for c in cmds {
       self.run_command(c);
     }
   }
 
+  /// Run a command and report errors
   pub fn run_command(&mut self, cmd: &Command) {
+    if let Err(err) = self.exec_command(cmd) {
+      self.error(&err);
+    }
+  }
+
+  /// Run commands, stopping at the first error
+  pub fn exec_commands(&mut self, cmds: &[Command]) -> Result<(), String> {
+    for c in cmds {
+      self.exec_command(c)?;
+    }
+    Ok(())
+  }
+
+  /// Run a command, returning errors to the caller.
+  /// The tree is updated even if it failed, since it may have changed files
+  pub fn exec_command(&mut self, cmd: &Command) -> Result<(), String> {
+    let res = self.dispatch_command(cmd);
+    self.update();
+    res
+  }
+
+  fn dispatch_command(&mut self, cmd: &Command) -> Result<(), String> {
     use Command::*;
     match cmd {
       Quit => {
         self.quit();
       }
-      Shell(cmd) => {
-        self.run_shell(cmd.as_str());
+      Suspend => {
+        term::suspend_process();
+        self.needs_clear = true;
       }
-      Open(path) => {
-        let cmd = self.config.open_cmd.clone();
-        let path = path.as_ref().unwrap_or_else(|| &self.tree.entry().path);
-        let _path = path.clone();
-        self.run_shell(cmd.as_str());
+      Shell(mode, cmd) => {
+        self.run_shell_mode(*mode, cmd.as_str())?;
+      }
+      EvalShell(cmd) => {
+        let output = self.run_shell(cmd.as_str())?;
+        let cmds = eval_shell_output(&output)?;
+        self.exec_commands(&cmds)?;
+      }
+      Open { path, with } => {
+        let path = self.target_path(path.as_ref());
+        let cmd = self.open_cmd(&path, with.as_deref())?;
+        let command = self.shell_command_for(&cmd, &path);
+        self.wait_for(command)?;
+        self.fire_hook(HookEvent::EntryOpened, &path.to_string_lossy());
         if self.config.quit_on_open {
           self.quit();
         }
       }
-      CmdStr(cmd) => match parse_cmds(cmd) {
-        Ok(cmds) => self.run_commands(&cmds),
-        Err(msg) => self.error(msg.as_str()),
-      },
-      Set(opt, val) => {
-        if let Err(e) = self.config.set_opt(opt, val) {
-          self.statusline.info.error(e.as_str());
+      OpenWith(path) => {
+        let path = self.target_path(path.as_ref());
+        self.popup = Some(self.open_with_popup(path));
+      }
+      OpenRule(rule) => {
+        self.open_rules.add(rule.clone());
+      }
+      StyleRule(pattern, style) => {
+        self.tree.style_rules.add(style_rules::StyleRule {
+          pattern: pattern.clone(),
+          style: self.config.parse_style(style)?,
+          spec: style.clone(),
+        });
+      }
+      SetColor(name, color) => {
+        self.config.set_color(name, color)?;
+        self.tree.style_rules.update_styles(&self.config);
+      }
+      Theme(name) => {
+        self.load_theme(name)?;
+      }
+      CmdStr(cmd) => {
+        let cmds = parse_cmds(cmd)?;
+        self.run_commands(&cmds);
+      }
+      Set(mode, opt, vals) => {
+        match mode {
+          SetMode::Set => self.config.set_values(opt, vals)?,
+          SetMode::Add => {
+            for val in vals {
+              self.config.add_opt(opt, val)?;
+            }
+          }
+          SetMode::Remove => {
+            for val in vals {
+              self.config.remove_opt(opt, val)?;
+            }
+          }
+          SetMode::Inc => match vals.as_slice() {
+            [delta] => self.config.inc_opt(opt, delta)?,
+            _ => return Err("usage: set -inc <option> <number>".to_string()),
+          },
         }
+        self.on_option_changed(opt);
+      }
+      Unset(opt) => {
+        self.config.unset_opt(opt)?;
+        self.on_option_changed(opt);
+      }
+      Reset(opt) => {
+        self.config.reset_opt(opt)?;
+        self.on_option_changed(opt);
+      }
+      Toggle(opt) => {
+        self.config.toggle_opt(opt)?;
+        self.on_option_changed(opt);
+      }
+      Get(opt) => {
+        let val = self.config.get_opt(opt)?;
+        self.statusline.info.info(&val);
+      }
+      Hook(hook) => {
+        self.hooks.add(hook.clone());
+      }
+      ReloadConfig => {
+        self.reload_config()?;
       }
       Echo(msg) => {
         self.statusline.info.info(msg.as_str());
@@ -208,25 +445,91 @@ impl<'a> App<'a> {
       Cd(path) => {
         let path = path.as_ref().unwrap_or_else(|| &self.tree.entry().path);
         let path = path.clone();
-        match std::env::set_current_dir(path.as_path()) {
-          Ok(()) => self
-            .tree
-            .change_root(&self.config, std::env::current_dir().unwrap()),
-          Err(err) => self.error(err.to_string().as_str()),
-        }
+        std::env::set_current_dir(path.as_path()).map_err(|e| e.to_string())?;
+        let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+        self.tree.change_root(&self.config, cwd);
       }
       MapKey(key, cmd) => {
         self.keymap.add_mapping(*key, (**cmd).clone());
       }
+      Expand(name, args) => {
+        // Expanded values are quoted in shell commands, so that they are
+        // passed as single words instead of being run as part of the command
+        let shell = matches!(name.as_str(), "shell" | "eval-shell");
+        let args = args
+          .iter()
+          .map(|a| {
+            if shell {
+              self.expand_quoted(a)
+            } else {
+              self.expand(a)
+            }
+          })
+          .collect::<Result<Vec<_>, _>>()?;
+        let cmd = build_cmd_args(name.clone(), args)?;
+        self.exec_command(&cmd)?;
+      }
+      If {
+        conds,
+        then,
+        otherwise,
+      } => {
+        let mut res = true;
+        for cond in conds {
+          res &= self.eval_condition(cond)?;
+        }
+        if res {
+          self.exec_commands(then)?;
+        } else {
+          self.exec_commands(otherwise)?;
+        }
+      }
+      Try { body, catch } => {
+        if let Err(err) = self.exec_commands(body) {
+          self.registers.insert("error".into(), err);
+          self.exec_commands(catch)?;
+        }
+      }
+      Jobs => {
+        let lines = self
+          .jobs
+          .running()
+          .iter()
+          .map(|j| {
+            PopupLine::new(format!("[{}] {} {}", j.id, j.pid, j.cmd), Style::default())
+              .with_action(Command::KillJob(j.id))
+          })
+          .collect();
+        self.popup = Some(Popup::new("jobs (<ret> to kill)", lines));
+      }
+      KillJob(id) => {
+        self.jobs.kill(*id)?;
+      }
+      Source(path) => {
+        let mut path = config_layers::expand_path(path);
+        // Relative paths are relative to the file being sourced
+        if let Some(dir) = self.sourcing.last().and_then(|f| f.parent()) {
+          path = dir.join(path);
+        }
+        self.run_script_file(&path)?;
+      }
+      Trust => {
+        let path = self
+          .untrusted_config
+          .take()
+          .ok_or("no untrusted project config found")?;
+        config_layers::trust(&path)?;
+        self.run_script_file(&path)?;
+      }
+      SetRegister(name, val) => {
+        self.registers.insert(name.clone(), val.clone());
+      }
       Rename(name) => {
         if let Some(name) = name {
-          let src = &self.tree.entry().path;
-          let mut dst = src.clone();
-          dst.set_file_name(name);
-          // TODO: Error handling
-          if !dst.exists() {
-            std::fs::rename(src, dst).unwrap();
-          }
+          let src = self.tree.entry().path.clone();
+          let dst = file_ops::rename(&src, name).map_err(|e| e.to_string())?;
+          self.update();
+          self.tree.select_path(&dst);
         } else {
           self.statusline.prompt(Box::new(RenamePrompt {
             old_name: self
@@ -234,36 +537,34 @@ impl<'a> App<'a> {
               .entry()
               .path
               .file_name()
-              .unwrap()
-              .to_string_lossy()
-              .into(),
+              .map(|n| n.to_string_lossy().into())
+              .unwrap_or_default(),
           }));
         }
       }
+      RenameRegex(pattern, replacement) => {
+        self.preview_regex_rename(pattern, replacement)?;
+      }
+      RenameAll(renames) => {
+        let summary = file_ops::rename_all(renames).map_err(|e| e.to_string())?;
+        self.tree.clear_marks();
+        self
+          .statusline
+          .info
+          .info(format!("renamed {} entries", summary.renamed).as_str());
+      }
       NewFile(name) => {
         if let Some(name) = name {
-          let mut path = self.tree.current_dir();
-          path.push(name);
-          // TODO: Error handling
-          if !path.exists() {
-            if name.ends_with('/') {
-              std::fs::create_dir_all(path).unwrap();
-            } else {
-              std::fs::write(path, "").unwrap();
-            }
-          }
+          let path = file_ops::new_file(&self.tree.current_dir(), name);
+          self.on_file_created(path.map_err(|e| e.to_string())?);
         } else {
           self.statusline.prompt(Box::new(NewFilePrompt {}));
         }
       }
       NewDir(name) => {
         if let Some(name) = name {
-          let mut path = self.tree.current_dir();
-          path.push(name);
-          // TODO: Error handling
-          if !path.exists() {
-            std::fs::create_dir_all(path).unwrap();
-          }
+          let path = file_ops::new_dir(&self.tree.current_dir(), name);
+          self.on_file_created(path.map_err(|e| e.to_string())?);
         } else {
           self.statusline.prompt(Box::new(NewDirPrompt {}));
         }
@@ -271,20 +572,115 @@ impl<'a> App<'a> {
 
       Delete { prompt } => {
         if !prompt {
-          let path = &self.tree.entry().path;
-          // TODO: Error handling
-          if path.is_dir() {
-            std::fs::remove_dir_all(path).unwrap();
-          } else {
-            std::fs::remove_file(path).unwrap();
+          let path = self.tree.entry().path.clone();
+          if path == self.tree.root_entry.path {
+            return Err(FileOpError::IsRoot(path).to_string());
           }
+          file_ops::delete(&path).map_err(|e| e.to_string())?;
+          self.update();
+          self.fire_hook(HookEvent::FileDeleted, &path.to_string_lossy());
         } else {
           self.statusline.prompt(Box::new(DeletePrompt {}));
         }
       }
+      ToggleMark => {
+        self.tree.toggle_mark();
+        self.tree.select_next();
+      }
+      ClearMarks => {
+        self.tree.clear_marks();
+      }
+      BulkRename => {
+        let summary = self.bulk_rename()?;
+        self.statusline.info.info(&summary);
+      }
+      Messages => {
+        let lines = self
+          .statusline
+          .info
+          .messages()
+          .map(|m| PopupLine::new(m.text.as_str(), self.config.msg_style(m.level)))
+          .collect();
+        self.popup = Some(Popup::new("messages", lines).select_last());
+      }
+      Help(name) => {
+        self.popup = Some(help_popup(name.as_deref(), &self.config)?);
+      }
+      Options => {
+        self.popup = Some(options_popup(&self.config));
+      }
+    }
+    Ok(())
+  }
+  /// The entry a command operates on: `path` relative to the current
+  /// directory of the tree, or the selected entry
+  fn target_path(&self, path: Option<&PathBuf>) -> PathBuf {
+    match path {
+      Some(path) => self.tree.current_dir().join(path),
+      None => self.tree.entry().path.clone(),
+    }
+  }
+
+  /// Reveal and select a newly created file
+  fn on_file_created(&mut self, path: PathBuf) {
+    self.tree.expand_to_path(&path);
+    self.update();
+    self.tree.select_path(&path);
+    self.fire_hook(HookEvent::FileCreated, &path.to_string_lossy());
+  }
+
+  /// Run the `OptionChanged` hooks, with the current name of the option
+  fn on_option_changed(&mut self, name: &str) {
+    if let Some(opt) = self.config.option(name) {
+      self.fire_hook(HookEvent::OptionChanged, &opt.name);
+    }
+  }
+
+  /// Evaluate a condition of the `if` command on the selected entry
+  fn eval_condition(&self, cond: &Condition) -> Result<bool, String> {
+    let entry = self.tree.entry();
+    Ok(match cond {
+      Condition::Dir => entry.is_dir,
+      Condition::File => entry.path.is_file(),
+      Condition::Link => entry.is_link,
+      Condition::Exec => entry
+        .path
+        .metadata()
+        .map(|md| !md.is_dir() && md.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false),
+      Condition::Glob(pattern) => glob_matches(pattern, &entry.path),
+      Condition::Mime(pattern) => glob::Pattern::new(pattern)
+        .map(|p| p.matches(entry.mime()))
+        .unwrap_or(false),
+      Condition::Opt(name, val) => self.config.get_opt(name)? == *val,
+      Condition::Not(cond) => !self.eval_condition(cond)?,
+    })
+  }
+
+  /// Report the result of a background job
+  pub fn on_job_done(&mut self, done: JobDone) {
+    let cmd = self.jobs.finish(done.id).map(|j| j.cmd).unwrap_or_default();
+    match done.status {
+      Ok(status) if status.success() => {
+        self
+          .statusline
+          .info
+          .info(format!("[{}] done: {}", done.id, cmd).as_str());
+      }
+      Ok(status) => {
+        for line in done.stderr.lines() {
+          self.statusline.info.log(MsgLevel::Error, line);
+        }
+        self
+          .statusline
+          .info
+          .error(format!("[{}] failed with {}: {}", done.id, status, cmd).as_str());
+      }
+      Err(err) => self.error(format!("[{}] {}", done.id, err).as_str()),
     }
     self.update();
   }
+
   pub fn error(&mut self, msg: &str) {
     self.statusline.info.error(msg)
   }
@@ -292,62 +688,618 @@ impl<'a> App<'a> {
     self.exit = true;
   }
 
+  /// Run the commands in a file.
+  /// Errors in the commands are reported, and do not stop the script
   pub fn run_script_file(&mut self, path: &Path) -> Result<(), String> {
-    let cmds = read_config_file(path)?;
-    self.run_commands(&cmds);
+    self.run_script_file_with(path, |script| script)
+  }
+
+  /// Run a config file, passing the parsed script through `filter` first
+  fn run_script_file_with(
+    &mut self,
+    path: &Path,
+    filter: fn(Script) -> Script,
+  ) -> Result<(), String> {
+    let path = path
+      .absolutize()
+      .map(PathBuf::from)
+      .unwrap_or_else(|_| path.to_path_buf());
+    if self.sourcing.contains(&path) {
+      return Err(format!("{} is already being sourced", path.display()));
+    }
+    let mtime = path.metadata().and_then(|m| m.modified()).ok();
+    self.config_files.retain(|(p, _)| *p != path);
+    self.config_files.push((path.clone(), mtime));
+    let script = read_config_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let script = filter(script);
+    let name = path.display().to_string();
+    self.sourcing.push(path);
+    self.run_script(&name, script);
+    self.sourcing.pop();
+    Ok(())
+  }
+
+  /// Run a parsed script. Parse and command errors are logged with their
+  /// location in `name`, and the first one is shown
+  fn run_script(&mut self, name: &str, script: Script) {
+    let mut errors: Vec<String> = script
+      .errors
+      .iter()
+      .map(|err| format!("{}:{}", name, err))
+      .collect();
+    for (pos, cmd) in &script.cmds {
+      if let Err(err) = self.exec_command(cmd) {
+        errors.push(format!("{}:{}:{}: {}", name, pos.line, pos.column, err));
+      }
+    }
+    match errors.len() {
+      0 => return,
+      1 => self.error(&errors[0]),
+      n => self.error(&format!(
+        "{} (and {} more errors, see :messages)",
+        errors[0],
+        n - 1
+      )),
+    }
+    for err in &errors[1..] {
+      self.statusline.info.log(MsgLevel::Error, err);
+    }
+  }
+
+  /// Reset the style options and load the theme `name`: a file in the
+  /// `themes` config directory, or a bundled theme
+  fn load_theme(&mut self, name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains('/') {
+      return Err(format!("invalid theme name {}", name));
+    }
+    let file = config_layers::find_theme(name);
+    let bundled = config_layers::BUNDLED_THEMES
+      .iter()
+      .find(|(n, _)| *n == name)
+      .map(|(_, theme)| *theme);
+    if file.is_none() && bundled.is_none() && name != "default" {
+      return Err(format!("unknown theme {}", name));
+    }
+    for opt in self.config.options() {
+      if opt.name.starts_with("style.") {
+        self.config.reset_opt(&opt.name)?;
+      }
+    }
+    match (file, bundled) {
+      (Some(file), _) => self.run_script_file_with(&file, config_layers::theme_script)?,
+      (None, Some(theme)) => self.run_script(
+        &format!("<theme {}>", name),
+        config_layers::theme_script(parse_script(theme)),
+      ),
+      (None, None) => {}
+    }
     Ok(())
   }
 
-  fn run_shell(&mut self, cmd: &str) {
-    let output = std::process::Command::new("sh")
+  /// Load the config layers: built-in defaults, system configs, the user
+  /// config, and the closest `.sidetreerc` from `root` upwards if it is trusted
+  pub fn load_config(&mut self, user_config: &Path, root: &Path) {
+    self.config_source = Some((user_config.to_path_buf(), root.to_path_buf()));
+    self.config_files.clear();
+    self.run_script(
+      "<default config>",
+      parse_script(config_layers::DEFAULT_CONFIG),
+    );
+    let mut files = config_layers::system_configs();
+    files.push(user_config.to_path_buf());
+    for file in files {
+      if let Err(err) = self.run_script_file(&file) {
+        self.error(&err);
+      }
+    }
+
+    let root = root.absolutize().map(PathBuf::from).unwrap_or_default();
+    if let Some(project) = config_layers::find_project_config(&root) {
+      let warning = match config_layers::trust_state(&project) {
+        TrustState::Trusted => {
+          if let Err(err) = self.run_script_file(&project) {
+            self.error(&err);
+          }
+          return;
+        }
+        TrustState::Changed => format!(
+          "Not loading {}, it changed since it was trusted. Run :trust to load it",
+          project.display()
+        ),
+        TrustState::Untrusted => format!(
+          "Not loading untrusted {}, run :trust to load it",
+          project.display()
+        ),
+      };
+      self.statusline.info.warning(&warning);
+      self.untrusted_config = Some(project);
+    }
+  }
+
+  /// Reset options, mappings and hooks, and load the config again
+  fn reload_config(&mut self) -> Result<(), String> {
+    if !self.sourcing.is_empty() {
+      return Err("reload-config cannot be used in config files".to_string());
+    }
+    let (user_config, root) = self.config_source.clone().ok_or("no config was loaded")?;
+    self.config = Config::default();
+    self.keymap = KeyMap::new();
+    self.hooks = Hooks::default();
+    self.open_rules = OpenRules::default();
+    self.tree.style_rules = StyleRules::default();
+    self.untrusted_config = None;
+    self.statusline.info.clear();
+    self.load_config(&user_config, &root);
+    if self.statusline.info.message().is_none() {
+      self.statusline.info.info("Reloaded config");
+    }
+    Ok(())
+  }
+
+  /// Whether one of the loaded config files was modified since it was run
+  fn config_changed(&self) -> bool {
+    self
+      .config_files
+      .iter()
+      .any(|(path, mtime)| path.metadata().and_then(|m| m.modified()).ok() != *mtime)
+  }
+
+  /// Resolve the expansions in a command argument
+  pub fn expand(&mut self, arg: &Argument) -> Result<String, String> {
+    match arg {
+      Argument::String(s) => Ok(s.clone()),
+      Argument::ConfOpt(name) => self.config.get_opt(name),
+      Argument::Value(name) => self.value(name),
+      Argument::Env(name) => Ok(std::env::var(name).unwrap_or_default()),
+      Argument::Register(name) => Ok(self.registers.get(name).cloned().unwrap_or_default()),
+      Argument::Shell(cmd) => {
+        let output = self.run_shell(cmd)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.trim_end_matches('\n').to_string())
+      }
+      Argument::Concat(parts) => parts.iter().map(|p| self.expand(p)).collect(),
+    }
+  }
+
+  /// Resolve the expansions in a command argument, quoting their values for
+  /// the shell
+  fn expand_quoted(&mut self, arg: &Argument) -> Result<String, String> {
+    match arg {
+      Argument::String(s) => Ok(s.clone()),
+      Argument::Concat(parts) => parts.iter().map(|p| self.expand_quoted(p)).collect(),
+      _ => self.expand(arg).map(|value| shell_quote(&value)),
+    }
+  }
+
+  /// Value of `%val{name}`
+  fn value(&self, name: &str) -> Result<String, String> {
+    let path_str = |p: &Path| p.to_string_lossy().into_owned();
+    match name {
+      "entry" => Ok(path_str(&self.tree.entry().path)),
+      "name" => Ok(
+        self
+          .tree
+          .entry()
+          .path
+          .file_name()
+          .map(|n| n.to_string_lossy().into_owned())
+          .unwrap_or_default(),
+      ),
+      "dir" => Ok(path_str(&self.tree.current_dir())),
+      "root" => Ok(path_str(&self.tree.root_entry.path)),
+      "mime" => Ok(self.tree.entry().mime().to_string()),
+      "hook_param" => self
+        .hook_param
+        .clone()
+        .ok_or_else(|| "%val{hook_param} is only set in hooks".to_string()),
+      _ => Err(format!("unknown value %val{{{}}}", name)),
+    }
+  }
+
+  /// The command to open `path` with: the rule named `with`, or the last
+  /// rule matching `path`, falling back to `open_cmd`
+  fn open_cmd(&self, path: &Path, with: Option<&str>) -> Result<String, String> {
+    let mime = self.mime_of(path);
+    let rule = match with {
+      Some(name) => match self.open_rules.get(name, path, mime) {
+        Some(rule) => Some(rule),
+        None if name == "default" => None,
+        None => return Err(format!("no open rule named {}", name)),
+      },
+      None => self.open_rules.find(path, mime),
+    };
+    Ok(rule.map_or_else(|| self.config.open_cmd.clone(), |r| r.cmd.clone()))
+  }
+
+  /// A popup to pick the open rule to open `path` with
+  fn open_with_popup(&self, path: PathBuf) -> Popup {
+    let open = |with: &str| Command::Open {
+      path: Some(path.clone()),
+      with: Some(with.to_string()),
+    };
+    let mime = self.mime_of(&path);
+    let rules = self.open_rules.candidates(&path, mime);
+    let width = rules.iter().map(|r| r.name.len()).max().unwrap_or(0).max(7);
+    let mut lines: Vec<PopupLine> = rules
+      .iter()
+      .map(|r| {
+        let text = format!("{:width$}  {}", r.name, r.cmd, width = width);
+        let style = if r.matches(&path, mime) {
+          Style::default()
+        } else {
+          self.config.style.icon
+        };
+        PopupLine::new(text, style).with_action(open(&r.name))
+      })
+      .collect();
+    if !rules.iter().any(|r| r.name == "default") {
+      let text = format!(
+        "{:width$}  {}",
+        "default",
+        self.config.open_cmd,
+        width = width
+      );
+      lines.push(PopupLine::new(text, Style::default()).with_action(open("default")));
+    }
+    let name = path
+      .file_name()
+      .unwrap_or(path.as_os_str())
+      .to_string_lossy();
+    Popup::new(format!("open {} with", name), lines)
+  }
+
+  /// The MIME type of `path`, cached if it is the selected entry
+  fn mime_of(&self, path: &Path) -> &'static str {
+    let entry = self.tree.entry();
+    if entry.path == path {
+      entry.mime()
+    } else {
+      mime::detect(path)
+    }
+  }
+
+  /// A `sh -c <cmd>` process with the `sidetree_*` variables set
+  fn shell_command(&self, cmd: &str) -> std::process::Command {
+    self.shell_command_for(cmd, &self.tree.entry().path)
+  }
+
+  /// A `sh -c <cmd>` process for `entry` instead of the selected entry
+  fn shell_command_for(&self, cmd: &str, entry: &Path) -> std::process::Command {
+    let mut command = std::process::Command::new("sh");
+    command
       .arg("-c")
       .arg(cmd)
       .arg("--")
-      .arg(self.tree.entry().path.to_str().unwrap_or(""))
+      .arg(entry.to_str().unwrap_or(""))
       .env(
         "sidetree_root",
         self.tree.root_entry.path.to_str().unwrap_or(""),
       )
-      .env(
-        "sidetree_entry",
-        self.tree.entry().path.to_str().unwrap_or(""),
-      )
+      .env("sidetree_entry", entry.to_str().unwrap_or(""))
+      .env("sidetree_mime", self.mime_of(entry))
       .env(
         "sidetree_dir",
         self.tree.current_dir().to_str().unwrap_or(""),
-      )
-      .output();
-    match output {
-      Err(err) => {
-        self.statusline.info.error(&err.to_string());
+      );
+    if let Some(param) = &self.hook_param {
+      command.env("sidetree_hook_param", param);
+    }
+    command
+  }
+
+  /// Run `command` attached to the terminal and wait for it to exit
+  fn run_interactive(
+    &mut self,
+    mut command: std::process::Command,
+  ) -> std::io::Result<std::process::ExitStatus> {
+    term::suspend();
+    let status = command.status();
+    term::resume();
+    self.needs_clear = true;
+    status
+  }
+
+  /// Edit the names of the marked entries, or the children of the current
+  /// directory, in `$EDITOR` and apply the changes
+  fn bulk_rename(&mut self) -> Result<String, String> {
+    let paths: Vec<PathBuf> = if self.tree.marked.is_empty() {
+      let mut paths: Vec<PathBuf> = std::fs::read_dir(self.tree.current_dir())
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| !self.config.is_hidden(p))
+        .collect();
+      paths.sort();
+      paths
+    } else {
+      self.tree.marked.iter().cloned().collect()
+    };
+    if paths.is_empty() {
+      return Err("bulk-rename: nothing to rename".into());
+    }
+    let contents: String = paths
+      .iter()
+      .map(|p| p.file_name().unwrap_or_default().to_string_lossy() + "\n")
+      .collect();
+    // A private directory, so other users cannot read or replace the file
+    let dir = util::make_temp_dir("sidetree-").map_err(|e| format!("bulk-rename: {}", e))?;
+    let tmp = dir.join("bulk-rename");
+    let edited = self.edit_file(&tmp, &contents);
+    let _ = std::fs::remove_dir_all(&dir);
+    let edited = edited?;
+
+    let names: Vec<&str> = edited.trim_end_matches('\n').lines().collect();
+    if names.len() != paths.len() {
+      return Err(format!(
+        "bulk-rename: expected {} lines, got {}",
+        paths.len(),
+        names.len()
+      ));
+    }
+    let renames = file_ops::renames_to_names(&paths, &names).map_err(|e| e.to_string())?;
+    let summary = file_ops::rename_all(&renames).map_err(|e| e.to_string())?;
+    self.tree.clear_marks();
+    Ok(match summary.cycles {
+      0 => format!("renamed {} entries", summary.renamed),
+      n => format!("renamed {} entries, broke {} cycles", summary.renamed, n),
+    })
+  }
+
+  /// Create `path` with `contents`, edit it in `$EDITOR` and return the result
+  fn edit_file(&mut self, path: &Path, contents: &str) -> Result<String, String> {
+    std::fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(path)
+      .and_then(|mut f| f.write_all(contents.as_bytes()))
+      .map_err(|e| e.to_string())?;
+    let editor = std::env::var("VISUAL")
+      .or_else(|_| std::env::var("EDITOR"))
+      .unwrap_or_else(|_| "vi".into());
+    let mut command = std::process::Command::new("sh");
+    command
+      .arg("-c")
+      .arg(format!("{} \"$0\"", editor))
+      .arg(path);
+    match self.run_interactive(command) {
+      Ok(status) if !status.success() => Err(format!("bulk-rename: editor failed with {}", status)),
+      Err(err) => Err(format!("bulk-rename: {}", err)),
+      Ok(_) => std::fs::read_to_string(path).map_err(|e| e.to_string()),
+    }
+  }
+
+  /// Show the renames of `rename -regex` in a popup, applied with `<ret>`.
+  /// Operates on marked entries, or the matching children of the current directory.
+  fn preview_regex_rename(&mut self, pattern: &str, replacement: &str) -> Result<(), String> {
+    let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+    let name_of = |p: &Path| {
+      p.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+    };
+    let mut paths: Vec<PathBuf> = if self.tree.marked.is_empty() {
+      std::fs::read_dir(self.tree.current_dir())
+        .map_err(|e| e.to_string())?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| !self.config.is_hidden(p))
+        .collect()
+    } else {
+      self.tree.marked.iter().cloned().collect()
+    };
+    paths.retain(|p| re.is_match(&name_of(p)));
+    paths.sort();
+    let renames: Vec<(PathBuf, String)> = paths
+      .into_iter()
+      .map(|p| {
+        let name = re.replace_all(&name_of(&p), replacement).into_owned();
+        (p, name)
+      })
+      .filter(|(src, name)| name_of(src) != *name)
+      .collect();
+    if renames.is_empty() {
+      return Err(format!("rename: nothing matches {}", pattern));
+    }
+
+    // Names with separators would move entries, so they are invalid here
+    let valid = |name: &str| file_ops::check_name(name).is_ok();
+    let targets: Vec<(PathBuf, PathBuf)> = renames
+      .iter()
+      .filter(|(_, name)| valid(name))
+      .map(|(src, name)| (src.clone(), src.with_file_name(name)))
+      .collect();
+    let conflicts = file_ops::conflicting_targets(&targets);
+    let has_conflicts = !conflicts.is_empty() || targets.len() != renames.len();
+    let apply = Command::RenameAll(targets);
+    let lines = renames
+      .iter()
+      .map(|(src, name)| {
+        let text = format!("{} → {}", name_of(src), name);
+        if !valid(name) || conflicts.contains(&src.with_file_name(name)) {
+          PopupLine::new(text, self.config.style.error)
+        } else if has_conflicts {
+          PopupLine::new(text, Style::default())
+        } else {
+          PopupLine::new(text, Style::default()).with_action(apply.clone())
+        }
+      })
+      .collect();
+    let title = if !has_conflicts {
+      "rename (<ret> to apply, q to cancel)"
+    } else {
+      "rename: conflicts, nothing will be renamed"
+    };
+    self.popup = Some(Popup::new(title, lines));
+    Ok(())
+  }
+
+  /// Run `cmd` and wait for it to finish.
+  /// Returns the output if it succeeded. The stderr of failed commands is
+  /// added to the message log.
+  fn run_shell(&mut self, cmd: &str) -> Result<std::process::Output, String> {
+    let command = self.shell_command(cmd);
+    self.wait_for(command)
+  }
+
+  /// Run `command` and wait for it to finish, like `run_shell`
+  fn wait_for(
+    &mut self,
+    mut command: std::process::Command,
+  ) -> Result<std::process::Output, String> {
+    let output = command.output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+      let stderr = String::from_utf8_lossy(&output.stderr);
+      for line in stderr.lines() {
+        self.statusline.info.log(MsgLevel::Error, line);
+      }
+      return Err(format!("Command failed with {}", output.status));
+    }
+    Ok(output)
+  }
+
+  fn run_shell_mode(&mut self, mode: ShellMode, cmd: &str) -> Result<(), String> {
+    match mode {
+      ShellMode::Silent => {
+        self.run_shell(cmd)?;
       }
-      Ok(output) => {
+      ShellMode::Show => {
+        let output = self
+          .shell_command(cmd)
+          .output()
+          .map_err(|e| e.to_string())?;
+        let mut lines: Vec<_> = String::from_utf8_lossy(&output.stdout)
+          .lines()
+          .map(|l| PopupLine::new(l, Style::default()))
+          .collect();
+        lines.extend(
+          String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .map(|l| PopupLine::new(l, self.config.style.error)),
+        );
+        self.popup = Some(Popup::new(format!("!{}", cmd), lines));
         if !output.status.success() {
-          self
-            .statusline
-            .info
-            .error(format!("Command failed with {}", output.status).as_str())
+          return Err(format!("Command failed with {}", output.status));
         }
       }
+      ShellMode::Async => {
+        let command = self.shell_command(cmd);
+        self.jobs.spawn(command, cmd)?;
+      }
+      ShellMode::Interactive => {
+        let command = self.shell_command(cmd);
+        let status = self.run_interactive(command).map_err(|e| e.to_string())?;
+        if !status.success() {
+          return Err(format!("Command failed with {}", status));
+        }
+      }
+      ShellMode::Echo => {
+        let output = self.run_shell(cmd)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let last = stdout.lines().rev().find(|l| !l.trim().is_empty());
+        self.statusline.info.info(last.unwrap_or(""));
+      }
     }
+    Ok(())
   }
 }
 
-pub struct ShellPrompt {}
+pub struct ShellPrompt {
+  mode: ShellMode,
+}
 
 impl Prompt for ShellPrompt {
   fn prompt_text(&self) -> &str {
-    "!"
+    match self.mode {
+      ShellMode::Show => "!!",
+      ShellMode::Interactive => "$",
+      _ => "!",
+    }
   }
   fn on_submit(&mut self, text: &str) -> Option<Command> {
-    Some(Command::Shell(text.to_string()))
+    Some(Command::Shell(self.mode, text.to_string()))
   }
   fn on_cancel(&mut self) -> Option<Command> {
     None
   }
 }
+/// Usage of a command, or a list of all commands if `name` is `None`.
+/// Pressing `<ret>` on a command in the list shows its usage
+fn help_popup(name: Option<&str>, config: &Config) -> Result<Popup, String> {
+  let name = match name {
+    Some(name) => name,
+    None => {
+      let lines = COMMANDS
+        .iter()
+        .map(|c| {
+          PopupLine::new(format!("{:<14} {}", c.name, c.doc), Style::default())
+            .with_action(Command::Help(Some(c.name.to_string())))
+        })
+        .collect();
+      return Ok(Popup::new("commands", lines));
+    }
+  };
+  if let Some(opt) = config.option(name) {
+    return Ok(option_help_popup(&opt, config));
+  }
+  let spec = find_cmd(name).ok_or_else(|| format!("unknown command or option {}", name))?;
+  let mut lines = vec![
+    PopupLine::new(format!("usage: {}", spec.usage), Style::default()),
+    PopupLine::new("", Style::default()),
+    PopupLine::new(spec.doc, Style::default()),
+  ];
+  if !spec.flags.is_empty() {
+    lines.push(PopupLine::new("", Style::default()));
+    for flag in spec.flags {
+      let text = format!("  {:<14} {}", flag.name, flag.doc);
+      lines.push(PopupLine::new(text, Style::default()));
+    }
+  }
+  Ok(Popup::new(format!("help {}", name), lines))
+}
+
+/// Type, default and current value, and documentation of an option
+fn option_help_popup(opt: &OptInfo, config: &Config) -> Popup {
+  let value = config.get_opt(&opt.name).unwrap_or_default();
+  let mut lines = vec![
+    PopupLine::new(format!("{}: {}", opt.name, opt.type_name), Style::default()),
+    PopupLine::new(format!("default: {}", opt.default), Style::default()),
+    PopupLine::new(format!("value:   {}", value), Style::default()),
+  ];
+  if let Some(range) = opt.range {
+    lines.push(PopupLine::new(
+      format!("range:   {}", range),
+      Style::default(),
+    ));
+  }
+  if !opt.values.is_empty() {
+    let values = format!("values:  {}", opt.values.join(", "));
+    lines.push(PopupLine::new(values, Style::default()));
+  }
+  lines.push(PopupLine::new("", Style::default()));
+  for line in opt.doc.lines() {
+    lines.push(PopupLine::new(line, Style::default()));
+  }
+  Popup::new(format!("help {}", opt.name), lines)
+}
+
+/// All options with their current values. `<ret>` shows the help of an option
+fn options_popup(config: &Config) -> Popup {
+  let options = config.options();
+  let width = options.iter().map(|o| o.name.len()).max().unwrap_or(0);
+  let lines = options
+    .iter()
+    .map(|opt| {
+      let value = config.get_opt(&opt.name).unwrap_or_default();
+      let text = format!("{:<width$} = {}", opt.name, value, width = width);
+      PopupLine::new(text, Style::default()).with_action(Command::Help(Some(opt.name.clone())))
+    })
+    .collect();
+  Popup::new("options", lines)
+}
 
-pub struct CmdPrompt {}
+pub struct CmdPrompt {
+  /// All options, for completion
+  options: Vec<OptInfo>,
+}
 
 impl Prompt for CmdPrompt {
   fn prompt_text(&self) -> &str {
@@ -356,6 +1308,9 @@ impl Prompt for CmdPrompt {
   fn on_submit(&mut self, text: &str) -> Option<Command> {
     Some(Command::CmdStr(text.to_string()))
   }
+  fn on_complete(&mut self, input: &str) -> Vec<String> {
+    complete(input, &self.options)
+  }
 }
 
 pub struct RenamePrompt {
@@ -415,3 +1370,22 @@ impl Prompt for DeletePrompt {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::app::*;
+
+  #[test]
+  fn open_rules_get_the_entry() {
+    let mut app = App::new(Cache::default());
+    let rule = r#"open-rule '*.pdf' 'printf "%s|%s" "$sidetree_entry" "$1"'"#;
+    app.exec_command(&parse_cmds(rule).unwrap()[0]).unwrap();
+    let path = Path::new("/tmp/a b.pdf");
+    let rule = app.open_rules.find(path, "application/pdf").unwrap();
+    let out = app.shell_command_for(&rule.cmd, path).output().unwrap();
+    assert_eq!(
+      String::from_utf8_lossy(&out.stdout),
+      "/tmp/a b.pdf|/tmp/a b.pdf"
+    );
+  }
+}
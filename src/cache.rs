@@ -1,33 +1,96 @@
 use crate::file_tree::ExpandedPaths;
 use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
+/// Version of the cache file format. Bump when the layout changes incompatibly.
+pub const CACHE_VERSION: u32 = 1;
+
 #[derive(Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct Cache {
+  #[serde(default)]
+  pub version: u32,
+
   #[serde(default)]
   pub selected_path: PathBuf,
-  
+
   #[serde(default)]
   pub expanded_paths: ExpandedPaths,
 }
 
 impl Cache {
+  pub fn new(selected_path: PathBuf, expanded_paths: ExpandedPaths) -> Cache {
+    Cache {
+      version: CACHE_VERSION,
+      selected_path,
+      expanded_paths,
+    }
+  }
+
+  /// Read the cache from `path`.
+  ///
+  /// A missing file gives an empty cache. Unreadable, corrupt or newer cache
+  /// files are reported as errors, so the caller can fall back to an empty
+  /// cache and warn about it.
   pub fn from_file(path: &Path) -> Result<Cache, String> {
     if !path.exists() {
-      File::create(path).expect("Cannot create config file");
+      return Ok(Cache::default());
     }
-    match std::fs::read_to_string(path) {
-      Ok(contents) => toml::from_str(&contents).map_err(|e| e.to_string()),
-      Err(err) => Err(err.to_string()),
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let cache: Cache = toml::from_str(&contents)
+      .map_err(|e| format!("Ignoring corrupt cache {}: {}", path.display(), e))?;
+    if cache.version > CACHE_VERSION {
+      return Err(format!(
+        "Ignoring cache {}: unsupported version {}",
+        path.display(),
+        cache.version
+      ));
+    }
+    Ok(cache)
+  }
+
+  /// Write the cache to `path`.
+  ///
+  /// `base` is the cache this instance was started with. Changes made since
+  /// then are merged into the current contents of the file, so several
+  /// instances can share one cache without overwriting each other's state.
+  /// The file is locked during the merge, and replaced atomically.
+  pub fn write_file(&self, base: &Cache, path: &Path) -> Result<(), String> {
+    let _lock = CacheLock::acquire(path)?;
+    let on_disk = Cache::from_file(path).unwrap_or_default();
+    let merged = self.merge(base, &on_disk);
+    let contents = toml::to_string(&merged).map_err(|e| e.to_string())?;
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(".{}.tmp", std::process::id()));
+    let tmp_path = PathBuf::from(tmp_path);
+    let res = File::create(&tmp_path)
+      .and_then(|mut f| {
+        f.write_all(contents.as_bytes())?;
+        f.sync_all()
+      })
+      .and_then(|_| std::fs::rename(&tmp_path, path));
+    if res.is_err() {
+      let _ = std::fs::remove_file(&tmp_path);
     }
+    res.map_err(|e| format!("Couldn't write cache {}: {}", path.display(), e))
   }
 
-  pub fn write_file(&self, path: &Path) {
-    std::fs::write(
-      path,
-      toml::to_string(self).expect("Couldn't serialize cache"),
-    )
-    .expect("Couldn't write cache to file");
+  /// Three way merge of `self` and `theirs`, using `base` as the common ancestor.
+  /// Paths expanded or collapsed in `self` since `base` win, everything else is
+  /// taken from `theirs`.
+  pub fn merge(&self, base: &Cache, theirs: &Cache) -> Cache {
+    let mut expanded_paths = theirs.expanded_paths.clone();
+    for p in base.expanded_paths.iter() {
+      if !self.expanded_paths.is_expanded(p) {
+        expanded_paths.collapse(p);
+      }
+    }
+    for p in self.expanded_paths.iter() {
+      expanded_paths.expand(p);
+    }
+    Cache::new(self.selected_path.clone(), expanded_paths)
   }
 
   pub fn default_file_path() -> PathBuf {
@@ -37,3 +100,80 @@ impl Cache {
       .expect("Cannot create cache directory")
   }
 }
+
+/// Exclusive advisory lock on `<cache>.lock`, released on drop.
+struct CacheLock {
+  file: File,
+}
+
+impl CacheLock {
+  fn acquire(path: &Path) -> Result<CacheLock, String> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = File::create(&lock_path).map_err(|e| e.to_string())?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+      return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(CacheLock { file })
+  }
+}
+
+impl Drop for CacheLock {
+  fn drop(&mut self) {
+    unsafe {
+      libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::cache::*;
+  use crate::util::TempDir;
+
+  fn cache(paths: &[&str]) -> Cache {
+    let mut exp = ExpandedPaths::default();
+    for p in paths {
+      exp.expand(Path::new(p));
+    }
+    Cache::new(PathBuf::from("/"), exp)
+  }
+
+  fn sorted(c: &Cache) -> Vec<PathBuf> {
+    let mut v: Vec<_> = c.expanded_paths.iter().cloned().collect();
+    v.sort();
+    v
+  }
+
+  #[test]
+  fn merge_keeps_other_instances_changes() {
+    let base = cache(&["/a", "/b"]);
+    let ours = cache(&["/a", "/c"]);
+    let theirs = cache(&["/a", "/b", "/d"]);
+    let merged = ours.merge(&base, &theirs);
+    assert_eq!(
+      sorted(&merged),
+      vec![
+        PathBuf::from("/a"),
+        PathBuf::from("/c"),
+        PathBuf::from("/d")
+      ]
+    );
+    assert_eq!(merged.version, CACHE_VERSION);
+  }
+
+  #[test]
+  fn write_and_read_back() {
+    let dir = TempDir::new("sidetree-cache-test-");
+    let path = dir.join("cache.toml");
+    std::fs::write(&path, "not [valid toml").unwrap();
+    assert!(Cache::from_file(&path).is_err());
+
+    let ours = cache(&["/x"]);
+    ours.write_file(&Cache::default(), &path).unwrap();
+    assert_eq!(
+      sorted(&Cache::from_file(&path).unwrap()),
+      vec![PathBuf::from("/x")]
+    );
+  }
+}
@@ -0,0 +1,196 @@
+use config_macros::ConfEnum;
+use lazy_static::lazy_static;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::Color;
+use tui::widgets::Widget;
+
+/// The colors a terminal can show, the type of the `color_mode` option
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ConfEnum)]
+pub enum ColorDepth {
+  /// Detected from the environment, see `DETECTED_DEPTH`
+  #[default]
+  Auto,
+  #[conf(name = "truecolor")]
+  TrueColor,
+  #[conf(name = "256")]
+  Ansi256,
+  #[conf(name = "16")]
+  Ansi16,
+  /// No colors at all, only attributes like bold
+  #[conf(name = "none")]
+  NoColor,
+}
+
+lazy_static! {
+  /// The color depth of the terminal, from the environment
+  pub static ref DETECTED_DEPTH: ColorDepth = detect_depth(
+    std::env::var("NO_COLOR").ok().as_deref(),
+    std::env::var("COLORTERM").ok().as_deref(),
+    std::env::var("TERM").ok().as_deref(),
+  );
+}
+
+/// Guess the color depth from `$NO_COLOR`, `$COLORTERM` and `$TERM`
+pub fn detect_depth(
+  no_color: Option<&str>,
+  colorterm: Option<&str>,
+  term: Option<&str>,
+) -> ColorDepth {
+  let term = term.unwrap_or("");
+  if no_color.is_some_and(|v| !v.is_empty()) || term == "dumb" {
+    ColorDepth::NoColor
+  } else if matches!(colorterm, Some("truecolor") | Some("24bit")) || term.ends_with("-direct") {
+    ColorDepth::TrueColor
+  } else if term.contains("256color") {
+    ColorDepth::Ansi256
+  } else {
+    ColorDepth::Ansi16
+  }
+}
+
+/// The 16 ANSI colors with their xterm values
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+  (Color::Black, (0, 0, 0)),
+  (Color::Red, (205, 0, 0)),
+  (Color::Green, (0, 205, 0)),
+  (Color::Yellow, (205, 205, 0)),
+  (Color::Blue, (0, 0, 238)),
+  (Color::Magenta, (205, 0, 205)),
+  (Color::Cyan, (0, 205, 205)),
+  (Color::Gray, (229, 229, 229)),
+  (Color::DarkGray, (127, 127, 127)),
+  (Color::LightRed, (255, 0, 0)),
+  (Color::LightGreen, (0, 255, 0)),
+  (Color::LightYellow, (255, 255, 0)),
+  (Color::LightBlue, (92, 92, 255)),
+  (Color::LightMagenta, (255, 0, 255)),
+  (Color::LightCyan, (0, 255, 255)),
+  (Color::White, (255, 255, 255)),
+];
+
+/// Levels of each component in the 6x6x6 color cube of the 256 colors
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+  let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+  d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+/// The RGB value of one of the 256 colors
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+  match i {
+    0..=15 => ANSI[i as usize].1,
+    16..=231 => {
+      let i = i - 16;
+      let level = |n: u8| CUBE_LEVELS[n as usize];
+      (level(i / 36), level(i / 6 % 6), level(i % 6))
+    }
+    _ => {
+      let v = 8 + 10 * (i - 232);
+      (v, v, v)
+    }
+  }
+}
+
+/// The closest of the 256 colors, ignoring the 16 ANSI colors whose values
+/// depend on the terminal
+fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+  let nearest_level = |v: u8| {
+    (0..6)
+      .min_by_key(|&i| (CUBE_LEVELS[i] as i32 - v as i32).abs())
+      .unwrap_or(0) as u8
+  };
+  let cube = 16 + 36 * nearest_level(rgb.0) + 6 * nearest_level(rgb.1) + nearest_level(rgb.2);
+  let avg = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+  let gray = 232 + ((avg.saturating_sub(3)) / 10).min(23) as u8;
+  if distance(indexed_rgb(gray), rgb) < distance(indexed_rgb(cube), rgb) {
+    gray
+  } else {
+    cube
+  }
+}
+
+fn nearest_16(rgb: (u8, u8, u8)) -> Color {
+  ANSI
+    .iter()
+    .min_by_key(|(_, v)| distance(*v, rgb))
+    .map(|(c, _)| *c)
+    .unwrap_or(Color::Reset)
+}
+
+/// The closest color to `color` the terminal can show
+pub fn downsample(color: Color, depth: ColorDepth) -> Color {
+  match (depth, color) {
+    (ColorDepth::TrueColor, c) => c,
+    (ColorDepth::NoColor, _) => Color::Reset,
+    (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(nearest_256((r, g, b))),
+    (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => nearest_16((r, g, b)),
+    (ColorDepth::Ansi16, Color::Indexed(i)) => nearest_16(indexed_rgb(i)),
+    (_, c) => c,
+  }
+}
+
+/// Maps the colors of everything rendered below it to a color depth.
+/// Rendered last, over the whole frame
+pub struct Downsample(pub ColorDepth);
+
+impl Widget for Downsample {
+  fn render(self, area: Rect, buf: &mut Buffer) {
+    if self.0 == ColorDepth::TrueColor {
+      return;
+    }
+    for y in area.top()..area.bottom() {
+      for x in area.left()..area.right() {
+        let cell = buf.get_mut(x, y);
+        cell.fg = downsample(cell.fg, self.0);
+        cell.bg = downsample(cell.bg, self.0);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::colors::*;
+
+  #[test]
+  fn depth_detection() {
+    use ColorDepth::*;
+    assert_eq!(
+      detect_depth(Some("1"), Some("truecolor"), Some("xterm")),
+      NoColor
+    );
+    assert_eq!(detect_depth(Some(""), Some("truecolor"), None), TrueColor);
+    assert_eq!(detect_depth(None, Some("24bit"), Some("screen")), TrueColor);
+    assert_eq!(detect_depth(None, None, Some("xterm-256color")), Ansi256);
+    assert_eq!(detect_depth(None, None, Some("xterm-direct")), TrueColor);
+    assert_eq!(detect_depth(None, None, Some("linux")), Ansi16);
+    assert_eq!(detect_depth(None, None, Some("dumb")), NoColor);
+
+    let mut config = crate::config::Config::default();
+    assert_eq!(config.color_depth(), *DETECTED_DEPTH);
+    config
+      .set_values("color_mode", &["256".to_string()])
+      .unwrap();
+    assert_eq!(config.color_depth(), Ansi256);
+    assert_eq!(config.get_opt("color_mode"), Ok("256".to_string()));
+  }
+
+  #[test]
+  fn colors_are_downsampled() {
+    use ColorDepth::*;
+    let nord = Color::Rgb(0x88, 0xC0, 0xD0);
+    assert_eq!(downsample(nord, TrueColor), nord);
+    assert_eq!(downsample(nord, Ansi256), Color::Indexed(110));
+    assert_eq!(
+      downsample(Color::Rgb(0x80, 0x80, 0x80), Ansi256),
+      Color::Indexed(244)
+    );
+    assert_eq!(downsample(Color::Rgb(250, 10, 10), Ansi16), Color::LightRed);
+    assert_eq!(downsample(Color::Indexed(21), Ansi16), Color::Blue);
+    assert_eq!(downsample(Color::Indexed(17), Ansi256), Color::Indexed(17));
+    assert_eq!(downsample(Color::Blue, Ansi16), Color::Blue);
+    assert_eq!(downsample(Color::Blue, NoColor), Color::Reset);
+  }
+}
@@ -1,6 +1,13 @@
+use crate::config::OptInfo;
+use crate::config_layers;
+use crate::hooks::{Hook, HookEvent};
 use crate::keymap::parse_key;
-use combine::Parser;
+use crate::open_rules::{EntryPattern, OpenRule};
+use crate::util::Glob;
+use combine::stream::position::{self, SourcePosition};
+use combine::{easy, EasyParser};
 use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
 use std::path::PathBuf;
 use termion::event::Key;
@@ -10,64 +17,916 @@ pub struct CmdManager {
   cmds: HashMap<String, CmdBlock>,
 }
 
-#[allow(dead_code)]
+/// A command argument as written in the script.
+/// Expansions are resolved when the command is executed.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Argument {
   String(String),
+  /// `%opt{name}`: value of a config option
   ConfOpt(String),
+  /// `%val{name}`: a value from the current state, i.e. the selected entry
+  Value(String),
+  /// `%env{name}`: an environment variable
+  Env(String),
+  /// `%reg{name}`: the contents of a register
+  Register(String),
+  /// `%sh{cmd}`: stdout of a shell command
+  Shell(String),
+  /// A double quoted string containing expansions
+  Concat(Vec<Argument>),
+}
+
+impl Argument {
+  /// The argument as a string, if it does not need expanding
+  pub fn literal(&self) -> Option<&str> {
+    match self {
+      Argument::String(s) => Some(s.as_str()),
+      _ => None,
+    }
+  }
+}
+
+/// How the output of a shell command is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellMode {
+  /// Discard the output, only report failure
+  Silent,
+  /// Show stdout and stderr in a popup
+  Show,
+  /// Echo the last line of stdout in the statusline
+  Echo,
+  /// Run in the background without blocking the UI
+  Async,
+  /// Run attached to the terminal, i.e. for editors and pagers
+  Interactive,
+}
+
+/// How `set` changes an option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetMode {
+  /// Replace the value
+  Set,
+  /// Add values to a list
+  Add,
+  /// Remove values from a list
+  Remove,
+  /// Add a number to a numeric option
+  Inc,
+}
+
+/// Condition of the `if` command, evaluated on the selected entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+  Dir,
+  File,
+  Link,
+  Exec,
+  Glob(String),
+  /// The MIME type matches a glob pattern
+  Mime(String),
+  /// Option `name` has the value `val`
+  Opt(String, String),
+  Not(Box<Condition>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command {
   Quit,
-  Shell(String),
-  Open(Option<PathBuf>),
+  Suspend,
+  Shell(ShellMode, String),
+  EvalShell(String),
+  /// Open an entry with an open rule, or `open_cmd`
+  Open {
+    path: Option<PathBuf>,
+    with: Option<String>,
+  },
+  /// Pick the open rule to open an entry with
+  OpenWith(Option<PathBuf>),
+  OpenRule(OpenRule),
+  /// A style rule, with the style parsed when it is run
+  StyleRule(EntryPattern, String),
+  SetColor(String, String),
+  Theme(String),
   CmdStr(String),
   Echo(String),
-  Set(String, String),
+  Set(SetMode, String, Vec<String>),
+  Unset(String),
+  /// Restore the default value of an option
+  Reset(String),
+  /// Invert a boolean option
+  Toggle(String),
+  /// Show the value of an option
+  Get(String),
   Cd(Option<PathBuf>),
   MapKey(Key, Box<Command>),
+  /// A command with arguments to expand before it is built and run
+  Expand(String, Vec<Argument>),
+  SetRegister(String, String),
+  Source(String),
+  Trust,
+  If {
+    conds: Vec<Condition>,
+    then: CmdBlock,
+    otherwise: CmdBlock,
+  },
+  Try {
+    body: CmdBlock,
+    catch: CmdBlock,
+  },
   Rename(Option<String>),
+  /// Preview renaming entries by regex substitution
+  RenameRegex(String, String),
+  /// Apply a previewed set of renames
+  RenameAll(Vec<(PathBuf, PathBuf)>),
   NewFile(Option<String>),
   NewDir(Option<String>),
-  Delete { prompt: bool },
+  Delete {
+    prompt: bool,
+  },
+  ToggleMark,
+  ClearMarks,
+  BulkRename,
+  Messages,
+  Jobs,
+  KillJob(usize),
+  /// Show the usage of a command or option, or list all commands
+  Help(Option<String>),
+  /// List all options with their values
+  Options,
+  /// Register a hook
+  Hook(Hook),
+  ReloadConfig,
   // NamedCmd(String, Vec<String>)
 }
 
 type CmdBlock = Vec<Command>;
 
-pub fn build_cmd(cmd: String, args: Vec<String>) -> Result<Command, String> {
-  match cmd.as_str() {
-    "quit" => Ok(Command::Quit),
-    "open" => Ok(Command::Open(None)),
-    "set" => Ok(Command::Set(args[0].clone(), args[1].clone())),
-    "echo" => Ok(Command::Echo(args.join(" "))),
-    "shell" => Ok(Command::Shell(args.join(" "))),
-    "cd" => Ok(Command::Cd(args.get(0).map(PathBuf::from))),
-    "map" => Ok(Command::MapKey(
-      parse_key(args[0].as_str()).map_err(|_| "could not parse key")?,
-      Box::new(build_cmd(args[1].clone(), args[2..].to_vec())?),
-    )),
-    "rename" => Ok(Command::Rename(args.get(0).cloned())),
-    "mkfile" => Ok(Command::NewFile(args.get(0).cloned())),
-    "mk" => Ok(Command::NewFile(args.get(0).cloned())),
-    "rm" => Ok(Command::Delete { prompt: true }),
-    _ => Err(format!("unknown command {}", cmd)),
+/// A flag accepted by a command, like `-show`
+pub struct FlagSpec {
+  pub name: &'static str,
+  /// Number of values following the flag
+  pub values: usize,
+  pub doc: &'static str,
+}
+
+/// Flags and positional arguments of a command, checked against its `CmdSpec`
+pub struct CmdArgs {
+  /// Flags in the order they were given, with their values
+  pub flags: Vec<(&'static str, Vec<String>)>,
+  pub args: Vec<String>,
+}
+
+impl CmdArgs {
+  fn values(&self, flag: &str) -> Option<&[String]> {
+    self
+      .flags
+      .iter()
+      .find(|(f, _)| *f == flag)
+      .map(|(_, v)| v.as_slice())
+  }
+}
+
+/// Description of a built-in command, used to validate its arguments and for
+/// `help` and completion
+pub struct CmdSpec {
+  pub name: &'static str,
+  pub usage: &'static str,
+  pub doc: &'static str,
+  pub flags: &'static [FlagSpec],
+  pub min_args: usize,
+  /// Maximum number of positional arguments, `None` if unbounded
+  pub max_args: Option<usize>,
+  build: fn(CmdArgs) -> Result<Command, String>,
+}
+
+impl CmdSpec {
+  pub fn usage_error(&self) -> String {
+    format!("usage: {}", self.usage)
+  }
+
+  /// Split leading flags from the positional arguments and check their number.
+  /// `--` ends the flags, and commands without flags take all arguments as is.
+  fn parse_args(&self, args: Vec<String>) -> Result<CmdArgs, String> {
+    let is_flag =
+      |a: &str| a.starts_with('-') && a[1..].starts_with(|c: char| c.is_alphabetic() || c == '-');
+    let mut flags = vec![];
+    let mut i = 0;
+    while !self.flags.is_empty() && i < args.len() && is_flag(&args[i]) {
+      if args[i] == "--" {
+        i += 1;
+        break;
+      }
+      let flag = self
+        .flags
+        .iter()
+        .find(|f| f.name == args[i])
+        .ok_or_else(|| format!("{}: unknown flag {}", self.name, args[i]))?;
+      let values = args
+        .get(i + 1..i + 1 + flag.values)
+        .ok_or_else(|| self.usage_error())?;
+      flags.push((flag.name, values.to_vec()));
+      i += 1 + flag.values;
+    }
+    let args = args[i..].to_vec();
+    if args.len() < self.min_args || self.max_args.is_some_and(|max| args.len() > max) {
+      return Err(self.usage_error());
+    }
+    Ok(CmdArgs { flags, args })
+  }
+}
+
+const NO_FLAGS: &[FlagSpec] = &[];
+
+/// All built-in commands
+pub static COMMANDS: &[CmdSpec] = &[
+  CmdSpec {
+    name: "quit",
+    usage: "quit",
+    doc: "Quit sidetree.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Quit),
+  },
+  CmdSpec {
+    name: "suspend",
+    usage: "suspend",
+    doc: "Suspend sidetree and return to the shell. Continue with `fg`.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Suspend),
+  },
+  CmdSpec {
+    name: "open",
+    usage: "open [-with <rule>] [path]",
+    doc: "Open `path` or the selected entry with the last `open-rule` matching \
+          it, or `open_cmd` if none matches.",
+    flags: &[FlagSpec {
+      name: "-with",
+      values: 1,
+      doc: "<rule>: open with the rule of that name, or `open_cmd` for `default`",
+    }],
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| {
+      Ok(Command::Open {
+        path: a.args.first().map(PathBuf::from),
+        with: a.values("-with").map(|v| v[0].clone()),
+      })
+    },
+  },
+  CmdSpec {
+    name: "open-with",
+    usage: "open-with [path]",
+    doc: "Pick the open rule to open `path` or the selected entry with in a popup.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| Ok(Command::OpenWith(a.args.first().map(PathBuf::from))),
+  },
+  CmdSpec {
+    name: "open-rule",
+    usage: "open-rule [-name <name>] [-mime] <pattern> <command>",
+    doc: "Open entries matching the glob `pattern` with a shell command, in \
+          which `$sidetree_entry` is the path. The rule is named after the first word of \
+          the command unless `-name` is given.",
+    flags: &[
+      FlagSpec {
+        name: "-name",
+        values: 1,
+        doc: "<name>: the name to pick the rule with in `open -with`",
+      },
+      FlagSpec {
+        name: "-mime",
+        values: 0,
+        doc: "match the pattern against the MIME type, i.e. `image/*`",
+      },
+    ],
+    min_args: 2,
+    max_args: Some(2),
+    build: |a| {
+      let pattern = EntryPattern::new(&a.args[0], a.values("-mime").is_some())?;
+      Ok(Command::OpenRule(OpenRule::new(
+        a.values("-name").map(|v| v[0].clone()),
+        pattern,
+        a.args[1].clone(),
+      )))
+    },
+  },
+  CmdSpec {
+    name: "set",
+    usage: "set [-add|-remove|-inc] <option> <value...>",
+    doc: "Set a config option. List options take any number of values.",
+    flags: &[
+      FlagSpec {
+        name: "-add",
+        values: 0,
+        doc: "add the values to a list option",
+      },
+      FlagSpec {
+        name: "-remove",
+        values: 0,
+        doc: "remove the values from a list option",
+      },
+      FlagSpec {
+        name: "-inc",
+        values: 0,
+        doc: "add the value, which may be negative, to a numeric option",
+      },
+    ],
+    min_args: 1,
+    max_args: None,
+    build: |a| {
+      let mode = match a.flags.last().map(|(f, _)| *f) {
+        Some("-add") => SetMode::Add,
+        Some("-remove") => SetMode::Remove,
+        Some("-inc") => SetMode::Inc,
+        _ => SetMode::Set,
+      };
+      let (name, values) = a
+        .args
+        .split_first()
+        .ok_or("usage: set <option> <value...>")?;
+      Ok(Command::Set(mode, name.clone(), values.to_vec()))
+    },
+  },
+  CmdSpec {
+    name: "unset",
+    usage: "unset <option>",
+    doc: "Clear an optional option. Other options are reset to their default.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Unset(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "reset",
+    usage: "reset <option>",
+    doc: "Restore the default value of an option.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Reset(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "toggle",
+    usage: "toggle <option>",
+    doc: "Invert a boolean option.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Toggle(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "get",
+    usage: "get <option>",
+    doc: "Show the value of an option in the status line.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Get(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "style-rule",
+    usage: "style-rule [-mime] <pattern> <style>",
+    doc: "Apply a style on top of the names of the entries matching the glob \
+          `pattern`. Later rules are applied on top of earlier ones.",
+    flags: &[FlagSpec {
+      name: "-mime",
+      values: 0,
+      doc: "match the pattern against the MIME type, i.e. `image/*`",
+    }],
+    min_args: 2,
+    max_args: Some(2),
+    build: |a| {
+      let pattern = EntryPattern::new(&a.args[0], a.values("-mime").is_some())?;
+      Ok(Command::StyleRule(pattern, a.args[1].clone()))
+    },
+  },
+  CmdSpec {
+    name: "set-color",
+    usage: "set-color <name> <color>",
+    doc: "Name a color, so it can be used in styles set after this.",
+    flags: NO_FLAGS,
+    min_args: 2,
+    max_args: Some(2),
+    build: |a| Ok(Command::SetColor(a.args[0].clone(), a.args[1].clone())),
+  },
+  CmdSpec {
+    name: "theme",
+    usage: "theme <name>",
+    doc: "Reset the `style` options and load a theme from the `themes` config \
+          directory, or one of the bundled themes. `default` only resets them.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Theme(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "echo",
+    usage: "echo [args...]",
+    doc: "Echo the arguments, joined by spaces, to the status line.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: None,
+    build: |a| Ok(Command::Echo(a.args.join(" "))),
+  },
+  CmdSpec {
+    name: "shell",
+    usage: "shell [-show|-echo|-async|-interactive] <command...>",
+    doc: "Run a shell command. Arguments are joined by spaces. By default the \
+          output is discarded.",
+    flags: &[
+      FlagSpec {
+        name: "-show",
+        values: 0,
+        doc: "show stdout and stderr in a popup",
+      },
+      FlagSpec {
+        name: "-echo",
+        values: 0,
+        doc: "echo the last line of stdout",
+      },
+      FlagSpec {
+        name: "-async",
+        values: 0,
+        doc: "run in the background",
+      },
+      FlagSpec {
+        name: "-interactive",
+        values: 0,
+        doc: "hand the terminal over to the command",
+      },
+    ],
+    min_args: 1,
+    max_args: None,
+    build: |a| {
+      let mode = match a.flags.last().map(|(f, _)| *f) {
+        Some("-show") => ShellMode::Show,
+        Some("-echo") => ShellMode::Echo,
+        Some("-async") => ShellMode::Async,
+        Some("-interactive") => ShellMode::Interactive,
+        _ => ShellMode::Silent,
+      };
+      Ok(Command::Shell(mode, a.args.join(" ")))
+    },
+  },
+  CmdSpec {
+    name: "eval-shell",
+    usage: "eval-shell <command...>",
+    doc: "Run a shell command, and run its output as sidetree commands.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: None,
+    build: |a| Ok(Command::EvalShell(a.args.join(" "))),
+  },
+  CmdSpec {
+    name: "cd",
+    usage: "cd [path]",
+    doc: "Change the root directory to `path`, or the selected directory.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| Ok(Command::Cd(a.args.first().map(PathBuf::from))),
+  },
+  CmdSpec {
+    name: "map",
+    usage: "map <key> <command> [args...]",
+    doc: "Map a key to a sidetree command, i.e. `map H cd ..`.",
+    flags: NO_FLAGS,
+    min_args: 2,
+    max_args: None,
+    build: |a| {
+      Ok(Command::MapKey(
+        parse_key(a.args[0].as_str()).map_err(|_| "could not parse key")?,
+        Box::new(build_cmd_args(a.args[1].clone(), a.args[2..].to_vec())?),
+      ))
+    },
+  },
+  CmdSpec {
+    name: "if",
+    usage: "if <conditions...> <commands> [else <commands>]",
+    doc: "Run commands if all conditions hold for the selected entry, \
+          otherwise the commands after `else`.",
+    flags: &[
+      FlagSpec {
+        name: "-not",
+        values: 0,
+        doc: "negate the next condition",
+      },
+      FlagSpec {
+        name: "-dir",
+        values: 0,
+        doc: "the entry is a directory",
+      },
+      FlagSpec {
+        name: "-file",
+        values: 0,
+        doc: "the entry is a regular file",
+      },
+      FlagSpec {
+        name: "-link",
+        values: 0,
+        doc: "the entry is a symlink",
+      },
+      FlagSpec {
+        name: "-exec",
+        values: 0,
+        doc: "the entry is executable",
+      },
+      FlagSpec {
+        name: "-glob",
+        values: 1,
+        doc: "the entry matches a glob pattern",
+      },
+      FlagSpec {
+        name: "-mime",
+        values: 1,
+        doc: "the MIME type of the entry matches a glob pattern, i.e. `text/*`",
+      },
+      FlagSpec {
+        name: "-opt",
+        values: 1,
+        doc: "<name>=<value>: the option has the value",
+      },
+    ],
+    min_args: 1,
+    max_args: Some(3),
+    build: build_if,
+  },
+  CmdSpec {
+    name: "try",
+    usage: "try <commands> [catch <commands>]",
+    doc: "Run commands, stopping at the first failing one. On failure the \
+          commands after `catch` are run, with the error in `%reg{error}`.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(3),
+    build: |a| match a.args.as_slice() {
+      [body] => Ok(Command::Try {
+        body: parse_cmds(body)?,
+        catch: vec![],
+      }),
+      [body, kw, catch] if kw == "catch" => Ok(Command::Try {
+        body: parse_cmds(body)?,
+        catch: parse_cmds(catch)?,
+      }),
+      _ => Err("usage: try <commands> [catch <commands>]".to_string()),
+    },
+  },
+  CmdSpec {
+    name: "source",
+    usage: "source <path>",
+    doc: "Run the commands in a file. Relative paths are relative to the \
+          sourcing file.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| Ok(Command::Source(a.args[0].clone())),
+  },
+  CmdSpec {
+    name: "reload-config",
+    usage: "reload-config",
+    doc: "Reset all options, mappings and hooks, and load the config files again.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::ReloadConfig),
+  },
+  CmdSpec {
+    name: "trust",
+    usage: "trust",
+    doc: "Trust and load the project-local `.sidetreerc` found on startup.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Trust),
+  },
+  CmdSpec {
+    name: "set-register",
+    usage: "set-register <name> [value...]",
+    doc: "Store a value in a register, which can be read with `%reg{name}`.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: None,
+    build: |a| {
+      Ok(Command::SetRegister(
+        a.args[0].clone(),
+        a.args[1..].join(" "),
+      ))
+    },
+  },
+  CmdSpec {
+    name: "rename",
+    usage: "rename [<name>|-regex <pattern> <replacement>]",
+    doc: "Rename the selected entry, prompting for the new name if none is \
+          given. With `-regex`, preview renaming the marked entries by regex \
+          substitution.",
+    flags: &[FlagSpec {
+      name: "-regex",
+      values: 2,
+      doc: "<pattern> <replacement>: substitute in the names of several entries",
+    }],
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| match a.values("-regex") {
+      None => Ok(Command::Rename(a.args.first().cloned())),
+      Some([pattern, replacement]) if a.args.is_empty() => {
+        regex::Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Command::RenameRegex(pattern.clone(), replacement.clone()))
+      }
+      Some(_) => Err("usage: rename [<name>|-regex <pattern> <replacement>]".to_string()),
+    },
+  },
+  CmdSpec {
+    name: "mkfile",
+    usage: "mkfile [name]",
+    doc: "Create a file in the selected directory, prompting for the name if \
+          none is given. A trailing `/` creates a directory.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| Ok(Command::NewFile(a.args.first().cloned())),
+  },
+  CmdSpec {
+    name: "mk",
+    usage: "mk [name]",
+    doc: "Alias for `mkfile`.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| Ok(Command::NewFile(a.args.first().cloned())),
+  },
+  CmdSpec {
+    name: "rm",
+    usage: "rm",
+    doc: "Delete the selected entry after confirmation.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Delete { prompt: true }),
+  },
+  CmdSpec {
+    name: "mark",
+    usage: "mark",
+    doc: "Toggle the mark on the selected entry, and select the next one.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::ToggleMark),
+  },
+  CmdSpec {
+    name: "clear-marks",
+    usage: "clear-marks",
+    doc: "Unmark all entries.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::ClearMarks),
+  },
+  CmdSpec {
+    name: "bulk-rename",
+    usage: "bulk-rename",
+    doc: "Rename the marked entries, or the children of the current \
+          directory, in `$EDITOR`.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::BulkRename),
+  },
+  CmdSpec {
+    name: "messages",
+    usage: "messages",
+    doc: "Show the log of messages and errors of this session.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Messages),
+  },
+  CmdSpec {
+    name: "jobs",
+    usage: "jobs",
+    doc: "List the running background jobs.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Jobs),
+  },
+  CmdSpec {
+    name: "kill-job",
+    usage: "kill-job <id>",
+    doc: "Kill the background job with the given id.",
+    flags: NO_FLAGS,
+    min_args: 1,
+    max_args: Some(1),
+    build: |a| {
+      let id = &a.args[0];
+      Ok(Command::KillJob(
+        id.parse().map_err(|_| format!("invalid job id {}", id))?,
+      ))
+    },
+  },
+  CmdSpec {
+    name: "help",
+    usage: "help [command|option]",
+    doc: "Show the usage of a command or the documentation of an option, or \
+          list all commands.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(1),
+    build: |a| Ok(Command::Help(a.args.first().cloned())),
+  },
+  CmdSpec {
+    name: "hook",
+    usage: "hook <event> [filter] <commands>",
+    doc: "Run commands when an event happens. The event parameter, i.e. the \
+          option name or path, is available as `%val{hook_param}` and \
+          `$sidetree_hook_param`, and can be filtered by a glob.",
+    flags: NO_FLAGS,
+    min_args: 2,
+    max_args: Some(3),
+    build: |a| {
+      let event = HookEvent::from_name(&a.args[0])?;
+      let (filter, cmds) = match a.args.as_slice() {
+        [_, cmds] => (None, cmds),
+        [_, filter, cmds] => (Some(Glob::new(filter)?), cmds),
+        _ => return Err("usage: hook <event> [filter] <commands>".to_string()),
+      };
+      if filter.is_some() && !event.has_param() {
+        return Err(format!("hook: {} cannot be filtered", event.name()));
+      }
+      Ok(Command::Hook(Hook {
+        event,
+        filter,
+        cmds: parse_cmds(cmds)?,
+      }))
+    },
+  },
+  CmdSpec {
+    name: "options",
+    usage: "options",
+    doc: "List all options with their current values.",
+    flags: NO_FLAGS,
+    min_args: 0,
+    max_args: Some(0),
+    build: |_| Ok(Command::Options),
+  },
+];
+
+/// Look up a built-in command by name
+pub fn find_cmd(name: &str) -> Option<&'static CmdSpec> {
+  COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Completions of the last word of a command line, given all options.
+/// Each candidate is the whole line with the word completed
+pub fn complete(input: &str, options: &[OptInfo]) -> Vec<String> {
+  let line = &input[input.rfind(';').map_or(0, |i| i + 1)..];
+  let mut words: Vec<&str> = line.split_whitespace().collect();
+  let word = match line.ends_with(char::is_whitespace) {
+    true => "",
+    false => words.pop().unwrap_or(""),
+  };
+  let args: Vec<&str> = words
+    .iter()
+    .skip(1)
+    .filter(|w| !w.starts_with('-'))
+    .copied()
+    .collect();
+  let names = || COMMANDS.iter().map(|c| c.name);
+  let opt_names = || options.iter().map(|o| o.name.as_str());
+  let themes = match words.first() {
+    Some(&"theme") => config_layers::theme_names(),
+    _ => vec![],
+  };
+  let candidates: Vec<&str> = match (words.first(), args.as_slice()) {
+    (None, _) => names().collect(),
+    (Some(name), _) if word.starts_with('-') => find_cmd(name)
+      .map(|c| c.flags.iter().map(|f| f.name).collect())
+      .unwrap_or_default(),
+    (Some(&"help"), []) => names().chain(opt_names()).collect(),
+    (Some(&"set" | &"unset" | &"reset" | &"toggle" | &"get"), []) => opt_names().collect(),
+    (Some(&"hook"), []) => HookEvent::ALL.iter().map(|e| e.name()).collect(),
+    (Some(&"theme"), []) => themes.iter().map(|t| t.as_str()).collect(),
+    (Some(&"hook"), ["OptionChanged"]) => opt_names().collect(),
+    (Some(&"set"), [opt, ..]) => options
+      .iter()
+      .find(|o| o.name == *opt)
+      .map(|o| o.values.clone())
+      .unwrap_or_default(),
+    _ => vec![],
+  };
+  let prefix = &input[..input.len() - word.len()];
+  candidates
+    .into_iter()
+    .filter(|c| c.starts_with(word))
+    .map(|c| format!("{}{}", prefix, c))
+    .collect()
+}
+
+/// Build an `if` command. Its conditions are flags, applied in order
+fn build_if(args: CmdArgs) -> Result<Command, String> {
+  let mut conds = vec![];
+  let mut negate = false;
+  for (flag, values) in args.flags {
+    let cond = match flag {
+      "-not" => {
+        negate = !negate;
+        continue;
+      }
+      "-dir" => Condition::Dir,
+      "-file" => Condition::File,
+      "-link" => Condition::Link,
+      "-exec" => Condition::Exec,
+      "-glob" => {
+        glob::Pattern::new(&values[0]).map_err(|e| format!("if: {}", e))?;
+        Condition::Glob(values[0].clone())
+      }
+      "-mime" => {
+        glob::Pattern::new(&values[0]).map_err(|e| format!("if: {}", e))?;
+        Condition::Mime(values[0].clone())
+      }
+      _ => {
+        let (name, val) = values[0]
+          .split_once('=')
+          .ok_or("if: expected -opt <name>=<value>")?;
+        Condition::Opt(name.to_string(), val.to_string())
+      }
+    };
+    conds.push(if negate {
+      Condition::Not(Box::new(cond))
+    } else {
+      cond
+    });
+    negate = false;
+  }
+  if negate {
+    return Err("if: -not must be followed by a condition".to_string());
+  }
+  match args.args.as_slice() {
+    [then] => Ok(Command::If {
+      conds,
+      then: parse_cmds(then)?,
+      otherwise: vec![],
+    }),
+    [then, kw, otherwise] if kw == "else" => Ok(Command::If {
+      conds,
+      then: parse_cmds(then)?,
+      otherwise: parse_cmds(otherwise)?,
+    }),
+    _ => Err("usage: if <conditions...> <commands> [else <commands>]".to_string()),
+  }
+}
+
+/// Build a command from parsed arguments.
+/// If any argument has expansions, building is deferred to when it is executed.
+pub fn build_cmd(cmd: String, mut args: Vec<Argument>) -> Result<Command, String> {
+  if cmd == "map" && args.len() >= 2 {
+    // The mapped command is built on its own, so its expansions are deferred
+    // until the key is pressed
+    let key = args[0]
+      .literal()
+      .ok_or("map: key must not contain expansions")?;
+    let key = parse_key(key).map_err(|_| "could not parse key")?;
+    let mapped = args[1]
+      .literal()
+      .ok_or("map: command name must not contain expansions")?
+      .to_string();
+    let mapped = build_cmd(mapped, args.split_off(2))?;
+    return Ok(Command::MapKey(key, Box::new(mapped)));
+  }
+  match args
+    .iter()
+    .map(|a| a.literal().map(String::from))
+    .collect::<Option<Vec<_>>>()
+  {
+    Some(args) => build_cmd_args(cmd, args),
+    None => Ok(Command::Expand(cmd, args)),
   }
 }
 
+/// Build a command from fully expanded arguments
+pub fn build_cmd_args(cmd: String, args: Vec<String>) -> Result<Command, String> {
+  let spec = find_cmd(&cmd).ok_or_else(|| format!("unknown command {}", cmd))?;
+  (spec.build)(spec.parse_args(args)?)
+}
+
 mod cmd_parser {
+  use super::Argument;
   use combine::error::Commit;
   use combine::error::ParseError;
+  use combine::error::StreamError;
   use combine::parser::char::char;
   use combine::parser::char::spaces;
   use combine::parser::combinator::ignore;
   use combine::parser::function::parser;
   use combine::parser::repeat::many;
   use combine::parser::repeat::many1;
-  use combine::parser::sequence::between;
   use combine::parser::token::any;
   use combine::parser::token::satisfy;
   use combine::parser::token::satisfy_map;
+  use combine::stream::StreamErrorFor;
   use combine::*;
 
   fn lex<Input, P>(p: P) -> impl Parser<Input, Output = P::Output>
@@ -94,6 +953,7 @@ mod cmd_parser {
           '\'' => '\'',
           '\\' => '\\',
           '/' => '/',
+          '%' => '%',
           'b' => '\u{0008}',
           'f' => '\u{000c}',
           'n' => '\n',
@@ -110,7 +970,7 @@ mod cmd_parser {
     })
   }
 
-  fn is_word_char(c: char) -> bool {
+  pub fn is_word_char(c: char) -> bool {
     if c.is_whitespace() {
       return false;
     }
@@ -123,54 +983,316 @@ mod cmd_parser {
     true
   }
 
-  fn arg<Input: Stream<Token = char>>() -> impl Parser<Input, Output = String> {
-    let double_quotes = || between(char('"'), lex(char('"')), many(cmd_str_char('"')));
-    let single_quotes = || between(char('\''), lex(char('\'')), many(cmd_str_char('\'')));
+  /// `content` between `open` and `close`. A missing `close` is reported at
+  /// `open` as `msg`, since the content runs to the end of the input
+  fn delimited<Input, P>(
+    open: char,
+    content: P,
+    close: char,
+    msg: &'static str,
+  ) -> impl Parser<Input, Output = P::Output>
+  where
+    P: Parser<Input>,
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    (char(open), content, optional(char(close))).and_then(move |(_, content, close)| match close {
+      Some(_) => Ok(content),
+      None => Err(StreamErrorFor::<Input>::message_static_message(msg)),
+    })
+  }
+
+  parser! {
+    /// Contents of a `{...}` block, which may contain nested braces
+    fn braced[Input]()(Input) -> String
+    where [Input: Stream<Token = char>]
+    {
+      delimited(
+        '{',
+        many::<Vec<String>, _, _>(choice((
+          many1(none_of("{}".chars())),
+          braced().map(|s| format!("{{{}}}", s)),
+        ))),
+        '}',
+        "unterminated block",
+      )
+      .map(|parts| parts.concat())
+    }
+  }
+
+  /// `%type{content}`
+  fn expansion<Input>() -> impl Parser<Input, Output = Argument>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    let kind = || many1::<String, _, _>(satisfy(|c: char| c.is_ascii_alphabetic()));
+    attempt(char('%').with(kind()).skip(look_ahead(char('{'))))
+      .and(braced())
+      .and_then(|(kind, content)| {
+        Ok(match kind.as_str() {
+          "opt" => Argument::ConfOpt(content),
+          "val" => Argument::Value(content),
+          "env" => Argument::Env(content),
+          "reg" => Argument::Register(content),
+          "sh" => Argument::Shell(content),
+          _ => {
+            return Err(StreamErrorFor::<Input>::message_format(format!(
+              "unknown expansion %{}",
+              kind
+            )))
+          }
+        })
+      })
+  }
+
+  /// `%{...}`: a literal string which may contain quotes and balanced braces,
+  /// useful for command blocks
+  fn percent_string<Input>() -> impl Parser<Input, Output = Argument>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    attempt(char('%').skip(look_ahead(char('{'))))
+      .with(braced())
+      .map(Argument::String)
+  }
+
+  /// Double quoted string. Literal text and expansions are collected into
+  /// `Argument::Concat` if there are any expansions
+  fn double_quoted<Input>() -> impl Parser<Input, Output = Argument>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    let part = || {
+      choice((
+        expansion().map(Some),
+        cmd_str_char('"').map(|c| Some(Argument::String(c.to_string()))),
+      ))
+    };
+    let parts = many::<Vec<_>, _, _>(part());
+    lex(delimited('"', parts, '"', "unterminated quote")).map(|parts| {
+      let mut res: Vec<Argument> = vec![];
+      for part in parts.into_iter().flatten() {
+        match (res.last_mut(), part) {
+          (Some(Argument::String(s)), Argument::String(c)) => s.push_str(&c),
+          (_, part) => res.push(part),
+        }
+      }
+      match res.len() {
+        0 => Argument::String(String::new()),
+        1 if res[0].literal().is_some() => res.remove(0),
+        _ => Argument::Concat(res),
+      }
+    })
+  }
+
+  fn arg<Input>() -> impl Parser<Input, Output = Argument>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    let single_quotes = || {
+      lex(delimited(
+        '\'',
+        many(cmd_str_char('\'')),
+        '\'',
+        "unterminated quote",
+      ))
+    };
     let word_char = || satisfy(is_word_char);
     let word = || many1(word_char());
-    choice!(double_quotes(), single_quotes(), word())
+    choice!(
+      double_quoted(),
+      single_quotes().map(Argument::String),
+      percent_string(),
+      expansion(),
+      word().map(Argument::String)
+    )
   }
 
-  pub fn cmds<Input>() -> impl Parser<Input, Output = Vec<(String, Vec<String>)>>
+  fn comment<Input>() -> impl Parser<Input, Output = ()>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    token('#').with(skip_many(satisfy(|x| x != '\n')))
+  }
+
+  /// Whitespace, command separators and comments between commands
+  pub fn skipped<Input>() -> impl Parser<Input, Output = ()>
   where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
   {
-    let comment = || token('#').with(many::<String, _, _>(satisfy(|x| x != '\n')));
-    let comments = || skip_many(comment());
     let cmd_sep = || one_of(";\n".chars());
-    let cmd = || lex(arg().skip(comments())).and(many(lex(arg().skip(comments()))));
-    let skipped = || spaces().skip(skip_many(ignore(lex(cmd_sep())).or(lex(ignore(comment())))));
-    skipped().with(many::<Vec<_>, _, _>(cmd().skip(skipped())))
+    spaces().skip(skip_many(ignore(lex(cmd_sep())).or(lex(comment()))))
+  }
+
+  /// A single command: its name and arguments
+  pub fn cmd<Input>() -> impl Parser<Input, Output = (String, Vec<Argument>)>
+  where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+  {
+    let comments = || skip_many(comment());
+    let name = arg().and_then(|a: Argument| match a {
+      Argument::String(s) => Ok(s),
+      _ => Err(StreamErrorFor::<Input>::message_static_message(
+        "command name must not contain expansions",
+      )),
+    });
+    lex(name.skip(comments())).and(many(lex(arg().skip(comments()))))
+  }
+}
+
+/// Location of a command or error in a script
+pub type ScriptPos = SourcePosition;
+
+/// An error in a script, with the position where it occurred
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+  pub pos: ScriptPos,
+  pub msg: String,
+}
+
+impl fmt::Display for ScriptError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}: {}", self.pos.line, self.pos.column, self.msg)
   }
 }
 
+/// A parsed script. Commands that failed to parse are left out and recorded
+/// in `errors`
+#[derive(Debug, Default)]
+pub struct Script {
+  pub cmds: Vec<(ScriptPos, Command)>,
+  pub errors: Vec<ScriptError>,
+}
+
+fn error_message(errors: &[easy::Error<char, &str>]) -> String {
+  let custom = errors.iter().find_map(|e| match e {
+    easy::Error::Message(msg) => Some(msg.to_string()),
+    easy::Error::Other(err) => Some(err.to_string()),
+    _ => None,
+  });
+  if let Some(msg) = custom {
+    return msg;
+  }
+  let unexpected = errors.iter().find_map(|e| match e {
+    easy::Error::Unexpected(info) => Some(info.to_string()),
+    _ => None,
+  });
+  let expected: Vec<String> = errors
+    .iter()
+    .filter_map(|e| match e {
+      easy::Error::Expected(info) => Some(info.to_string()),
+      _ => None,
+    })
+    .collect();
+  match (unexpected, expected.is_empty()) {
+    (Some(u), true) => format!("unexpected {}", u),
+    (Some(u), false) => format!("unexpected {}, expected {}", u, expected.join(" or ")),
+    (None, false) => format!("expected {}", expected.join(" or ")),
+    (None, true) => "invalid command".to_string(),
+  }
+}
+
+/// Parse a script, continuing with the next line after errors
+pub fn parse_script(input: &str) -> Script {
+  let mut script = Script::default();
+  let mut stream = position::Stream::with_positioner(input, SourcePosition::default());
+  loop {
+    if let Ok((_, rest)) = cmd_parser::skipped().easy_parse(stream.clone()) {
+      stream = rest;
+    }
+    if stream.input.is_empty() {
+      break;
+    }
+    let start = stream.positioner;
+    match cmd_parser::cmd().easy_parse(stream) {
+      Ok(((name, args), rest)) => {
+        match build_cmd(name, args) {
+          Ok(cmd) => script.cmds.push((start, cmd)),
+          Err(msg) => script.errors.push(ScriptError { pos: start, msg }),
+        }
+        stream = rest;
+      }
+      Err(err) => {
+        // Unterminated quotes and blocks are reported at their opening
+        // character. They run to the end of the input, so parsing continues
+        // after the line of the error
+        let line = err.position.line;
+        script.errors.push(ScriptError {
+          pos: err.position,
+          msg: error_message(&err.errors),
+        });
+        let next_line = input
+          .split_inclusive('\n')
+          .take(line as usize)
+          .map(str::len)
+          .sum();
+        if next_line >= input.len() {
+          break;
+        }
+        stream = position::Stream {
+          input: &input[next_line..],
+          positioner: SourcePosition {
+            line: line + 1,
+            column: 1,
+          },
+        };
+      }
+    }
+  }
+  script
+}
+
+/// Quote `arg` if needed, so it is parsed back as a single argument
+pub fn quote_arg(arg: &str) -> String {
+  let bare = !arg.is_empty()
+    && !arg.starts_with(['\'', '"', '%'])
+    && arg.chars().all(cmd_parser::is_word_char);
+  if bare {
+    arg.to_string()
+  } else {
+    format!("'{}'", arg.replace('\\', "\\\\").replace('\'', "\\'"))
+  }
+}
+
+/// Parse commands, failing on the first error
 pub fn parse_cmds(input: &str) -> Result<CmdBlock, String> {
-  match cmd_parser::cmds().parse(input) {
-    Err(_) => Err("error parsing command".to_string()),
-    Ok((mut cmds, "")) => cmds.drain(..).map(|(c, a)| build_cmd(c, a)).collect(),
-    Ok((_, rem)) => Err(format!("Unexpected content after commands: {}", rem)),
+  let script = parse_script(input);
+  match script.errors.first() {
+    Some(err) => Err(err.to_string()),
+    None => Ok(script.cmds.into_iter().map(|(_, cmd)| cmd).collect()),
   }
 }
 
-pub fn read_config_file(path: &Path) -> Result<CmdBlock, String> {
-  let contents = std::fs::read_to_string(path);
-  match contents {
-    Ok(contents) => parse_cmds(contents.as_str()),
-    Err(err) => Err(err.to_string()),
+/// The commands printed by the shell command of `eval-shell`
+pub fn eval_shell_output(output: &std::process::Output) -> Result<CmdBlock, String> {
+  if !output.status.success() {
+    return Err(format!("Command failed with {}", output.status));
   }
+  parse_cmds(&String::from_utf8_lossy(&output.stdout)).map_err(|msg| format!("eval-shell: {}", msg))
+}
+
+pub fn read_config_file(path: &Path) -> Result<Script, String> {
+  std::fs::read_to_string(path)
+    .map(|contents| parse_script(&contents))
+    .map_err(|err| err.to_string())
 }
 
 #[cfg(test)]
 mod test {
   use crate::commands::*;
-  use combine::StreamOnce;
+  use combine::{Parser, StreamOnce};
 
-  fn cmd_parse_test(input: &str) -> Result<(String, Vec<String>), <&str as StreamOnce>::Error> {
-    cmd_parser::cmds().parse(input).map(|(cmds, rem)| {
+  fn cmd_parse_test(input: &str) -> Result<(String, Vec<Argument>), <&str as StreamOnce>::Error> {
+    cmd_parser::cmd().parse(input).map(|(cmd, rem)| {
       assert!(rem.is_empty());
-      assert!(cmds.len() == 1);
-      cmds.first().unwrap().clone()
+      cmd
     })
   }
 
@@ -186,19 +1308,303 @@ mod test {
       res,
       Ok((
         "cmd".to_string(),
-        vec!["arg \"1".to_string(), "arg '2".to_string()]
+        vec![
+          Argument::String("arg \"1".to_string()),
+          Argument::String("arg '2".to_string())
+        ]
+      ))
+    );
+  }
+  #[test]
+  fn parse_cmd_shell_flags() {
+    assert_eq!(
+      parse_cmds("shell -show wc -l"),
+      Ok(vec![Command::Shell(ShellMode::Show, "wc -l".to_string())])
+    );
+    assert_eq!(
+      parse_cmds("shell -- -echo"),
+      Ok(vec![Command::Shell(ShellMode::Silent, "-echo".to_string())])
+    );
+    assert!(parse_cmds("shell -foo ls").is_err());
+  }
+  #[test]
+  fn parse_cmd_expansions() {
+    let res = cmd_parse_test("echo %val{entry} '%opt{x}' \"in %env{HOME}/x\" %sh{echo {a}}");
+    assert_eq!(
+      res,
+      Ok((
+        "echo".to_string(),
+        vec![
+          Argument::Value("entry".to_string()),
+          Argument::String("%opt{x}".to_string()),
+          Argument::Concat(vec![
+            Argument::String("in ".to_string()),
+            Argument::Env("HOME".to_string()),
+            Argument::String("/x".to_string()),
+          ]),
+          Argument::Shell("echo {a}".to_string()),
+        ]
       ))
     );
+    assert!(cmd_parse_test("echo %foo{x}").is_err());
+    assert_eq!(
+      parse_cmds("echo 100% %"),
+      Ok(vec![Command::Echo("100% %".to_string())])
+    );
+    assert_eq!(
+      parse_cmds("map x cd %reg{a}"),
+      Ok(vec![Command::MapKey(
+        Key::Char('x'),
+        Box::new(Command::Expand(
+          "cd".to_string(),
+          vec![Argument::Register("a".to_string())]
+        ))
+      )])
+    );
+  }
+  #[test]
+  fn parse_cmd_rename_regex() {
+    assert_eq!(
+      parse_cmds(r"rename -regex '(\\d+)\\.txt$' '${1}.md'"),
+      Ok(vec![Command::RenameRegex(
+        r"(\d+)\.txt$".to_string(),
+        "${1}.md".to_string()
+      )])
+    );
+    assert!(parse_cmds("rename -regex '(' x").is_err());
+    assert!(parse_cmds("rename -regex x").is_err());
+  }
+  #[test]
+  fn parse_cmd_if_try() {
+    assert_eq!(
+      parse_cmds("if -not -dir -glob '*.rs' %{echo 'a;b'} else %{cd; open}"),
+      Ok(vec![Command::If {
+        conds: vec![
+          Condition::Not(Box::new(Condition::Dir)),
+          Condition::Glob("*.rs".to_string())
+        ],
+        then: vec![Command::Echo("a;b".to_string())],
+        otherwise: vec![
+          Command::Cd(None),
+          Command::Open {
+            path: None,
+            with: None
+          }
+        ],
+      }])
+    );
+    assert_eq!(
+      parse_cmds("try %{quit} catch 'echo failed'"),
+      Ok(vec![Command::Try {
+        body: vec![Command::Quit],
+        catch: vec![Command::Echo("failed".to_string())],
+      }])
+    );
+    assert!(parse_cmds("if -opt show_hidden %{quit}").is_err());
+    assert_eq!(
+      parse_cmds("if -dir -not %{quit}"),
+      Err("1:1: if: -not must be followed by a condition".to_string())
+    );
+    assert!(parse_cmds("if -dir %{quit} otherwise %{quit}").is_err());
+    assert!(parse_cmds("try %{quit} catch").is_err());
+  }
+  #[test]
+  fn parse_cmd_eval_shell() {
+    let sh = |script: &str| {
+      std::process::Command::new("sh")
+        .args(["-c", script])
+        .output()
+        .unwrap()
+    };
+    assert_eq!(
+      eval_shell_output(&sh("echo 'cd /tmp'; echo \"echo 'a b'\"")),
+      Ok(vec![
+        Command::Cd(Some(PathBuf::from("/tmp"))),
+        Command::Echo("a b".to_string())
+      ])
+    );
+    assert_eq!(eval_shell_output(&sh("true")), Ok(vec![]));
+    assert_eq!(
+      eval_shell_output(&sh("echo foo")),
+      Err("eval-shell: 1:1: unknown command foo".to_string())
+    );
+    assert_eq!(
+      eval_shell_output(&sh("echo quit; exit 3")),
+      Err("Command failed with exit status: 3".to_string())
+    );
+  }
+  #[test]
+  fn quoted_args_round_trip() {
+    for arg in [
+      "a", "a b", "", "it's", "x\\'y", "%{a}", "'a", "#a", "a;b", "\"a", "*.o",
+    ] {
+      let quoted = quote_arg(arg);
+      assert_eq!(
+        parse_cmds(&format!("echo {}", quoted)),
+        Ok(vec![Command::Echo(arg.to_string())]),
+        "{}",
+        quoted
+      );
+    }
+    assert_eq!(quote_arg("*.o"), "*.o");
   }
   #[test]
   fn parse_cmd_multiple() {
     assert_eq!(
       parse_cmds("quit; open"),
-      Ok(vec![Command::Quit, Command::Open(None)])
+      Ok(vec![
+        Command::Quit,
+        Command::Open {
+          path: None,
+          with: None
+        }
+      ])
     );
     assert_eq!(
       parse_cmds("quit\nopen"),
-      Ok(vec![Command::Quit, Command::Open(None)])
+      Ok(vec![
+        Command::Quit,
+        Command::Open {
+          path: None,
+          with: None
+        }
+      ])
+    );
+  }
+  #[test]
+  fn parse_script_errors() {
+    let script = parse_script("quit\necho 'abc\nfoo x\n  open\necho \"x");
+    assert_eq!(
+      script.cmds,
+      vec![
+        (SourcePosition { line: 1, column: 1 }, Command::Quit),
+        (
+          SourcePosition { line: 4, column: 3 },
+          Command::Open {
+            path: None,
+            with: None
+          }
+        ),
+      ]
+    );
+    let errors: Vec<String> = script.errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(
+      errors,
+      vec![
+        "2:6: unterminated quote",
+        "3:1: unknown command foo",
+        "5:6: unterminated quote",
+      ]
+    );
+    // Quotes spanning lines are reported at the opening quote too
+    let errors = parse_script("quit\n  echo a \"b\nc\nd").errors;
+    assert_eq!(errors[0].to_string(), "2:10: unterminated quote");
+    assert_eq!(
+      parse_cmds("cd %{a"),
+      Err("1:5: unterminated block".to_string())
+    );
+    assert_eq!(
+      parse_cmds("if -dir %{\n  cd %{x}\n"),
+      Err("1:10: unterminated block".to_string())
+    );
+  }
+  #[test]
+  fn parse_cmd_arity() {
+    assert_eq!(
+      parse_cmds("set"),
+      Err("1:1: usage: set [-add|-remove|-inc] <option> <value...>".to_string())
+    );
+    assert_eq!(
+      parse_cmds("set -inc indent_width -1; toggle show_hidden"),
+      Ok(vec![
+        Command::Set(
+          SetMode::Inc,
+          "indent_width".to_string(),
+          vec!["-1".to_string()]
+        ),
+        Command::Toggle("show_hidden".to_string()),
+      ])
+    );
+    assert!(parse_cmds("get").is_err());
+    assert_eq!(
+      parse_cmds("open -with zathura a.pdf; open-rule '*.pdf' 'zathura \"$sidetree_entry\"'"),
+      Ok(vec![
+        Command::Open {
+          path: Some(PathBuf::from("a.pdf")),
+          with: Some("zathura".to_string())
+        },
+        Command::OpenRule(OpenRule {
+          name: "zathura".to_string(),
+          pattern: EntryPattern::new("*.pdf", false).unwrap(),
+          cmd: "zathura \"$sidetree_entry\"".to_string(),
+        }),
+      ])
+    );
+    assert!(parse_cmds("open-rule '*.pdf'").is_err());
+    assert!(parse_cmds("style-rule '[a' red").is_err());
+    assert_eq!(
+      parse_cmds("style-rule -mime 'image/*' magenta+i"),
+      Ok(vec![Command::StyleRule(
+        EntryPattern::new("image/*", true).unwrap(),
+        "magenta+i".to_string()
+      )])
+    );
+    assert_eq!(
+      parse_cmds("set -add hide_patterns '*.o' '*.a'"),
+      Ok(vec![Command::Set(
+        SetMode::Add,
+        "hide_patterns".to_string(),
+        vec!["*.o".to_string(), "*.a".to_string()]
+      )])
+    );
+    assert!(parse_cmds("map x").is_err());
+    assert!(parse_cmds("kill-job").is_err());
+    assert!(parse_cmds("quit now").is_err());
+    assert!(parse_cmds("rename -regex x").is_err());
+    assert_eq!(
+      parse_cmds("shell -echo -- -x"),
+      Ok(vec![Command::Shell(ShellMode::Echo, "-x".to_string())])
+    );
+    assert_eq!(
+      parse_cmds("echo -n -1"),
+      Ok(vec![Command::Echo("-n -1".to_string())])
+    );
+  }
+  #[test]
+  fn completion() {
+    let options = crate::config::Config::default().options();
+    assert_eq!(complete("bulk", &options), vec!["bulk-rename"]);
+    assert_eq!(complete("quit; he", &options), vec!["quit; help"]);
+    assert_eq!(complete("help kill", &options), vec!["help kill-job"]);
+    assert_eq!(
+      complete("help sh", &options),
+      vec!["help shell", "help show_hidden"]
+    );
+    assert_eq!(complete("set sh", &options), vec!["set show_hidden"]);
+    assert_eq!(complete("toggle sh", &options), vec!["toggle show_hidden"]);
+    assert_eq!(
+      complete("set sort_by s", &options),
+      vec!["set sort_by size"]
+    );
+    assert_eq!(complete("set -a", &options), vec!["set -add"]);
+    assert_eq!(complete("shell -a", &options), vec!["shell -async"]);
+    assert!(complete("echo a", &options).is_empty());
+  }
+  #[test]
+  fn parse_cmd_hook() {
+    assert_eq!(
+      parse_cmds("hook OptionChanged show_hidden %{echo %val{hook_param}}"),
+      Ok(vec![Command::Hook(Hook {
+        event: HookEvent::OptionChanged,
+        filter: Some(Glob::new("show_hidden").unwrap()),
+        cmds: vec![Command::Expand(
+          "echo".to_string(),
+          vec![Argument::Value("hook_param".to_string())]
+        )],
+      })])
     );
+    assert!(parse_cmds("hook Startup '*' quit").is_err());
+    assert!(parse_cmds("hook EntryOpened '[a' quit").is_err());
+    assert!(parse_cmds("hook Foo quit").is_err());
   }
 }
@@ -1,41 +1,406 @@
+use crate::colors::{ColorDepth, DETECTED_DEPTH};
+use crate::commands::quote_arg;
+use crate::prompt::MsgLevel;
+use crate::util::Glob;
 use combine::parser::EasyParser;
-use config_macros::ConfParsable;
+use config_macros::{ConfEnum, ConfParsable};
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::Duration;
 use tui::style::{Color, Modifier, Style};
 
 // Config definition
-#[derive(Default, ConfParsable)]
+#[derive(ConfParsable)]
 pub struct Config {
+  /// Whether to show hidden files (file names starting with `.`)
+  #[conf(default = "false")]
   pub show_hidden: bool,
+  /// Glob patterns of files to hide like dotfiles, unless `show_hidden` is set.
+  /// Patterns containing a `/` are matched against the whole path.
+  /// Example: `set -add hide_patterns '*.o'`
+  pub hide_patterns: Vec<Glob>,
+  /// Order of the entries in a directory. Directories are always listed first.
+  /// `size` and `mtime` put the largest and newest entries first.
+  #[conf(default = "name")]
+  pub sort_by: SortBy,
+  /// Number of columns each level of the tree is indented by
+  #[conf(default = "2", range = "0..=8")]
+  pub indent_width: usize,
+  /// The shell command to run to open a file, i.e. on the `:open` command,
+  /// or when pressing `<return>` on a file.
+  /// Used for files without a matching `open-rule`.
+  /// Example: `set open_cmd 'xdg-open "$sidetree_entry"'`
+  #[conf(default = "xdg-open")]
   pub open_cmd: String,
+  /// Whether to quit sidetree after `:open` (or pressing `<return>` on a file)
+  #[conf(default = "false")]
   pub quit_on_open: bool,
+  /// Whether to enable file icons by extension.
+  /// May or may not be supported by your fonts.
+  #[conf(default = "true")]
   pub file_icons: bool,
-  pub icon_style: Style,
-  pub dir_name_style: Style,
-  pub file_name_style: Style,
-  pub highlight_style: Style,
-  pub link_style: Style,
+  /// Styles of entries and messages
+  #[conf(nested)]
+  pub style: StyleConfig,
+  /// Whether to style entries like `ls`, using the colors in `$LS_COLORS`,
+  /// i.e. from `dircolors`. Entries without a color there keep the styles of
+  /// the `style` options.
+  #[conf(default = "false")]
+  pub ls_colors: bool,
+  /// The colors the terminal can show. Other colors are drawn as the closest
+  /// color it has, i.e. `rgb:` colors become one of the 256 colors. With
+  /// `auto`, this is `truecolor` if `$COLORTERM` is `truecolor` or `24bit`,
+  /// `256` if `$TERM` contains `256color`, and `16` otherwise. Colors are
+  /// disabled if `$NO_COLOR` is set or `$TERM` is `dumb`.
+  #[conf(default = "auto")]
+  pub color_mode: ColorDepth,
+  /// Whether to reload the config, like `reload-config`, when one of the
+  /// loaded config files is modified
+  #[conf(default = "false")]
+  pub watch_config: bool,
+  /// Milliseconds after which a message in the status line is cleared.
+  /// `0` keeps messages until they are replaced.
+  #[conf(default = "5000")]
+  pub message_timeout: i32,
+  /// Number of messages kept for the `messages` command. The oldest messages
+  /// are dropped first.
+  #[conf(default = "1000", range = "1..")]
+  pub message_log_size: usize,
+  /// Colors named with `set-color`
+  #[conf(skip)]
+  palette: Palette,
+}
+
+/// Style options, named `style.<name>`
+#[derive(ConfParsable)]
+pub struct StyleConfig {
+  /// Style of the file/folder icon
+  #[conf(default = "darkgray")]
+  pub icon: Style,
+  /// Style of directory names
+  #[conf(default = "lightblue+b")]
+  pub dir: Style,
+  /// Style of non-directory names
+  #[conf(default = "reset")]
+  pub file: Style,
+  /// Style of the highlighted entry
+  #[conf(default = "+r")]
+  pub highlight: Style,
+  /// Style of symlink names, applied on top of the other name styles, so `+r`
+  /// could be a good option, or alternatively `blue,reset+r`
+  #[conf(default = "cyan+b")]
+  pub link: Style,
+  /// Style of broken symlink names, applied on top of `style.link`
+  #[conf(default = "red")]
+  pub broken_link: Style,
+  /// Style of executable file names, applied on top of `style.file`
+  #[conf(default = "green+b")]
+  pub exec: Style,
+  /// Style of setuid and setgid file names, applied on top of `style.file`
+  #[conf(default = "white,red")]
+  pub setuid: Style,
+  /// Style of named pipe names, applied on top of `style.file`
+  #[conf(default = "yellow")]
+  pub fifo: Style,
+  /// Style of socket names, applied on top of `style.file`
+  #[conf(default = "magenta+b")]
+  pub socket: Style,
+  /// Style of block and character device names, applied on top of `style.file`
+  #[conf(default = "yellow+b")]
+  pub device: Style,
+  /// Style applied on top of the names of hidden entries, when they are shown
+  #[conf(default = "+d")]
+  pub hidden: Style,
+  /// Style applied on top of the names of marked entries
+  #[conf(default = "yellow")]
+  pub mark: Style,
+  /// Style of informational messages, i.e. from `echo`
+  #[conf(default = "reset")]
+  pub info: Style,
+  /// Style of warnings
+  #[conf(default = "yellow")]
+  pub warning: Style,
+  /// Style of error messages
+  #[conf(default = "red+b")]
+  pub error: Style,
+}
+
+/// Old names of options that moved into a namespace
+const ALIASES: &[(&str, &str)] = &[
+  ("icon_style", "style.icon"),
+  ("dir_name_style", "style.dir"),
+  ("file_name_style", "style.file"),
+  ("highlight_style", "style.highlight"),
+  ("link_style", "style.link"),
+  ("mark_style", "style.mark"),
+  ("info_style", "style.info"),
+  ("warning_style", "style.warning"),
+  ("error_style", "style.error"),
+];
+
+/// The current name of an option, resolving old names
+fn resolve_alias(name: &str) -> &str {
+  ALIASES
+    .iter()
+    .find(|(alias, _)| *alias == name)
+    .map_or(name, |(_, name)| name)
+}
+
+/// Whether `name` is one of the `style` options
+pub fn is_style_opt(name: &str) -> bool {
+  resolve_alias(name).starts_with("style.")
+}
+
+/// Order of the entries in a directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ConfEnum)]
+pub enum SortBy {
+  #[default]
+  Name,
+  Extension,
+  Size,
+  Mtime,
+}
+
+/// Documentation of an option, generated from the `Config` definition
+pub struct OptInfo {
+  pub name: String,
+  pub type_name: &'static str,
+  pub doc: &'static str,
+  pub default: String,
+  /// Valid values of numeric options
+  pub range: Option<&'static str>,
+  /// All values of enum options
+  pub values: Vec<&'static str>,
 }
 
 impl Config {
-  pub fn set_opt(&mut self, name: &str, val: &str) -> Result<(), String> {
-    self.get_child_mut(name)?.set_opt(val)
+  /// Set an option to a list of values. Options that are not lists take
+  /// exactly one value
+  pub fn set_values(&mut self, name: &str, vals: &[String]) -> Result<(), String> {
+    let name = resolve_alias(name);
+    match vals {
+      [val] if is_style_opt(name) => {
+        let style = self
+          .parse_style(val)
+          .map_err(|e| format!("{}: {}", name, e))?;
+        self.modify_opt(name, |opt| opt.set_opt(&style.get_opt()))?;
+        self.palette.styles.insert(name.to_string(), val.clone());
+        Ok(())
+      }
+      _ => self.modify_opt(name, |opt| opt.set_values(vals)),
+    }
+  }
+  /// Add a value to a list option
+  pub fn add_opt(&mut self, name: &str, val: &str) -> Result<(), String> {
+    self.modify_opt(name, |opt| opt.add_opt(val))
+  }
+  /// Remove a value from a list option
+  pub fn remove_opt(&mut self, name: &str, val: &str) -> Result<(), String> {
+    self.modify_opt(name, |opt| opt.remove_opt(val))
+  }
+  /// Add `delta` to a numeric option
+  pub fn inc_opt(&mut self, name: &str, delta: &str) -> Result<(), String> {
+    self.modify_opt(name, |opt| opt.inc_opt(delta))
+  }
+  /// Invert a boolean option
+  pub fn toggle_opt(&mut self, name: &str) -> Result<(), String> {
+    self.modify_opt(name, |opt| opt.toggle_opt())
+  }
+  /// Clear an optional option, or restore the default value of other options
+  pub fn unset_opt(&mut self, name: &str) -> Result<(), String> {
+    let name = resolve_alias(name);
+    if self.get_child_mut(name)?.unset_opt().is_err() {
+      self.reset_opt(name)?;
+    }
+    self.palette.styles.remove(name);
+    Ok(())
+  }
+  /// Restore the default value of an option
+  pub fn reset_opt(&mut self, name: &str) -> Result<(), String> {
+    let name = resolve_alias(name);
+    ConfTree::reset_opt(self, name)?;
+    self.palette.styles.remove(name);
+    Ok(())
+  }
+
+  /// Apply `f` to an option, restoring the previous value if `f` fails or the
+  /// result is out of the option's range
+  fn modify_opt<F>(&mut self, name: &str, f: F) -> Result<(), String>
+  where
+    F: FnOnce(&mut dyn ConfOpt) -> Result<(), String>,
+  {
+    let name = resolve_alias(name);
+    let old = OptValue::save(self.get_child(name)?);
+    let res = f(self.get_child_mut(name)?)
+      .map_err(|e| format!("{}: {}", name, e))
+      .and_then(|()| self.check_opt(name));
+    if res.is_err() {
+      OptValue::restore(self.get_child_mut(name)?, old);
+    }
+    res
   }
-  #[allow(dead_code)]
   pub fn get_opt(&self, name: &str) -> Result<String, String> {
-    Ok(self.get_child(name)?.get_opt())
+    Ok(self.get_child(resolve_alias(name))?.get_opt())
+  }
+  /// All options with their documentation
+  pub fn options(&self) -> Vec<OptInfo> {
+    ConfTree::options(self)
+  }
+  pub fn option(&self, name: &str) -> Option<OptInfo> {
+    let name = resolve_alias(name);
+    self.options().into_iter().find(|o| o.name == name)
+  }
+
+  /// Whether `path` is hidden, i.e. a dotfile or matching `hide_patterns`,
+  /// unless `show_hidden` is set
+  pub fn is_hidden(&self, path: &Path) -> bool {
+    !self.show_hidden && self.is_hidden_file(path)
+  }
+
+  /// Whether `path` is a dotfile or matches `hide_patterns`
+  pub fn is_hidden_file(&self, path: &Path) -> bool {
+    let dotfile = path
+      .file_name()
+      .map(|n| n.to_string_lossy().starts_with('.'))
+      .unwrap_or(false);
+    dotfile || self.hide_patterns.iter().any(|p| p.matches_path(path))
+  }
+
+  pub fn msg_style(&self, level: MsgLevel) -> Style {
+    match level {
+      MsgLevel::Info => self.style.info,
+      MsgLevel::Warning => self.style.warning,
+      MsgLevel::Error => self.style.error,
+    }
+  }
+
+  /// The colors to render with, resolving `color_mode auto`
+  pub fn color_depth(&self) -> ColorDepth {
+    match self.color_mode {
+      ColorDepth::Auto => *DETECTED_DEPTH,
+      depth => depth,
+    }
+  }
+
+  /// How long messages stay in the statusline, `None` to keep them until replaced
+  pub fn message_timeout(&self) -> Option<Duration> {
+    if self.message_timeout > 0 {
+      Some(Duration::from_millis(self.message_timeout as u64))
+    } else {
+      None
+    }
+  }
+}
+
+/// Colors named with `set-color`, and the values of the style options using
+/// them, to resolve them again when a color changes
+#[derive(Default)]
+struct Palette {
+  colors: HashMap<String, Color>,
+  /// Style options set to a value that can contain color names, by option
+  styles: HashMap<String, String>,
+}
+
+impl Config {
+  /// Name a color, so it can be used in styles like the built-in color names.
+  /// Style options already using the name get the new color
+  pub fn set_color(&mut self, name: &str, color: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+      && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+      && !(name.starts_with("color") && name[5..].starts_with(|c: char| c.is_ascii_digit()));
+    if !valid {
+      return Err(format!("invalid color name {}", name));
+    }
+    if style_parser::named(name).is_some() {
+      return Err(format!("cannot redefine the built-in color {}", name));
+    }
+    let color = self.parse_color(color)?;
+    let mut colors = self.palette.colors.clone();
+    colors.insert(name.to_string(), color);
+    // Only change anything once every style got its new color
+    let styles = self
+      .palette
+      .styles
+      .iter()
+      .map(|(opt, val)| Ok((opt.clone(), parse_style_with(val, &colors)?)))
+      .collect::<Result<Vec<_>, String>>()?;
+    for (opt, style) in styles {
+      self.get_child_mut(&opt)?.set_opt(&style.get_opt())?;
+    }
+    self.palette.colors = colors;
+    Ok(())
+  }
+
+  /// Parse a style, with the colors named with `set-color`
+  pub fn parse_style(&self, input: &str) -> Result<Style, String> {
+    parse_style_with(input, &self.palette.colors)
+  }
+
+  /// Parse a color, with the colors named with `set-color`
+  pub fn parse_color(&self, input: &str) -> Result<Color, String> {
+    parse_color_with(input, &self.palette.colors)
   }
 }
 
 // Lib functions
 
-trait ConfOpt {
+/// Saving and restoring the value of an option as is, for any option type
+pub(crate) trait OptValue {
+  fn save(&self) -> Box<dyn Any>;
+  fn restore(&mut self, val: Box<dyn Any>);
+}
+
+impl<T: Clone + 'static> OptValue for T {
+  fn save(&self) -> Box<dyn Any> {
+    Box::new(self.clone())
+  }
+  fn restore(&mut self, val: Box<dyn Any>) {
+    if let Ok(val) = val.downcast::<T>() {
+      *self = *val;
+    }
+  }
+}
+
+pub(crate) trait ConfOpt: OptValue {
   fn set_opt(&mut self, val: &str) -> Result<(), String>;
   fn get_opt(&self) -> String;
+  fn set_values(&mut self, vals: &[String]) -> Result<(), String> {
+    match vals {
+      [val] => self.set_opt(val),
+      _ => Err("expected a single value".to_string()),
+    }
+  }
+  fn add_opt(&mut self, _val: &str) -> Result<(), String> {
+    Err("not a list".to_string())
+  }
+  fn remove_opt(&mut self, _val: &str) -> Result<(), String> {
+    Err("not a list".to_string())
+  }
+  fn unset_opt(&mut self) -> Result<(), String> {
+    Err("cannot be unset".to_string())
+  }
+  fn inc_opt(&mut self, _delta: &str) -> Result<(), String> {
+    Err("not a number".to_string())
+  }
+  fn toggle_opt(&mut self) -> Result<(), String> {
+    Err("not a boolean".to_string())
+  }
+  /// All valid values, for options with a fixed set of values
+  fn values(&self) -> Vec<&'static str> {
+    vec![]
+  }
 }
 
 trait ConfTree {
   fn get_child(&self, name: &str) -> Result<&dyn ConfOpt, String>;
   fn get_child_mut(&mut self, name: &str) -> Result<&mut dyn ConfOpt, String>;
+  fn options(&self) -> Vec<OptInfo>;
+  /// Restore the default value of an option
+  fn reset_opt(&mut self, name: &str) -> Result<(), String>;
+  /// Check that the value of an option is valid
+  fn check_opt(&self, name: &str) -> Result<(), String>;
 }
 
 fn parse_opt<T: std::str::FromStr>(val: &str) -> Result<T, String> {
@@ -45,11 +410,28 @@ fn parse_opt<T: std::str::FromStr>(val: &str) -> Result<T, String> {
   }
 }
 
+/// Add a signed delta to an integer, failing if the result does not fit
+fn inc_int<T>(val: T, delta: &str) -> Result<T, String>
+where
+  T: Copy + TryFrom<i64> + Into<i64>,
+{
+  let delta: i64 = parse_opt(delta)?;
+  val
+    .into()
+    .checked_add(delta)
+    .and_then(|v| T::try_from(v).ok())
+    .ok_or_else(|| "value out of bounds".to_string())
+}
+
 impl ConfOpt for bool {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
     *self = parse_opt(val)?;
     Ok(())
   }
+  fn toggle_opt(&mut self) -> Result<(), String> {
+    *self = !*self;
+    Ok(())
+  }
   fn get_opt(&self) -> String {
     if *self {
       "true".to_string()
@@ -64,11 +446,105 @@ impl ConfOpt for i32 {
     *self = parse_opt(val)?;
     Ok(())
   }
+  fn inc_opt(&mut self, delta: &str) -> Result<(), String> {
+    *self = inc_int(*self, delta)?;
+    Ok(())
+  }
+  fn get_opt(&self) -> String {
+    self.to_string()
+  }
+}
+
+impl ConfOpt for u16 {
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    *self = parse_opt(val)?;
+    Ok(())
+  }
+  fn inc_opt(&mut self, delta: &str) -> Result<(), String> {
+    *self = inc_int(*self, delta)?;
+    Ok(())
+  }
+  fn get_opt(&self) -> String {
+    self.to_string()
+  }
+}
+
+impl ConfOpt for usize {
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    *self = parse_opt(val)?;
+    Ok(())
+  }
+  fn inc_opt(&mut self, delta: &str) -> Result<(), String> {
+    let val = inc_int(*self as i64, delta)?;
+    *self = usize::try_from(val).map_err(|_| "value out of bounds".to_string())?;
+    Ok(())
+  }
   fn get_opt(&self) -> String {
     self.to_string()
   }
 }
 
+/// Lists are set to all values given to `set`, and modified with `set -add`
+/// and `set -remove`
+impl<T: ConfOpt + Default + Clone + 'static> ConfOpt for Vec<T> {
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    self.set_values(&[val.to_string()])
+  }
+  fn get_opt(&self) -> String {
+    self
+      .iter()
+      .map(|v| quote_arg(&v.get_opt()))
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+  fn set_values(&mut self, vals: &[String]) -> Result<(), String> {
+    let mut res = vec![];
+    for val in vals {
+      let mut v = T::default();
+      v.set_opt(val)?;
+      res.push(v);
+    }
+    *self = res;
+    Ok(())
+  }
+  fn add_opt(&mut self, val: &str) -> Result<(), String> {
+    let mut v = T::default();
+    v.set_opt(val)?;
+    self.push(v);
+    Ok(())
+  }
+  fn remove_opt(&mut self, val: &str) -> Result<(), String> {
+    let mut v = T::default();
+    v.set_opt(val)?;
+    let val = v.get_opt();
+    self.retain(|v| v.get_opt() != val);
+    Ok(())
+  }
+  fn values(&self) -> Vec<&'static str> {
+    T::default().values()
+  }
+}
+
+/// Optional values are empty when unset
+impl<T: ConfOpt + Default + Clone + 'static> ConfOpt for Option<T> {
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    let mut v = T::default();
+    v.set_opt(val)?;
+    *self = Some(v);
+    Ok(())
+  }
+  fn get_opt(&self) -> String {
+    self.as_ref().map(|v| v.get_opt()).unwrap_or_default()
+  }
+  fn unset_opt(&mut self) -> Result<(), String> {
+    *self = None;
+    Ok(())
+  }
+  fn values(&self) -> Vec<&'static str> {
+    T::default().values()
+  }
+}
+
 impl ConfOpt for String {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
     *self = val.to_string();
@@ -79,6 +555,16 @@ impl ConfOpt for String {
   }
 }
 
+impl ConfOpt for Glob {
+  fn set_opt(&mut self, val: &str) -> Result<(), String> {
+    *self = Glob::new(val)?;
+    Ok(())
+  }
+  fn get_opt(&self) -> String {
+    self.as_str().to_string()
+  }
+}
+
 impl ConfOpt for Style {
   fn set_opt(&mut self, val: &str) -> Result<(), String> {
     *self = parse_style(val)?;
@@ -156,11 +642,13 @@ mod style_parser {
   use combine::parser::char::*;
   use combine::parser::EasyParser;
   use combine::*;
+  use std::collections::HashMap;
   use tui::style::Color;
   use tui::style::Modifier;
   use tui::style::Style;
 
-  pub fn color<'a>() -> impl EasyParser<&'a str, Output = Color> {
+  /// A color, named colors are looked up in `palette` if they are not built-in
+  pub fn color(palette: &HashMap<String, Color>) -> impl EasyParser<&str, Output = Color> {
     let hex_byte = || {
       count_min_max(2, 2, hex_digit()).and_then(|x: String| {
         u8::from_str_radix(x.as_str(), 16).map_err(|_| error::UnexpectedParse::Unexpected)
@@ -182,30 +670,39 @@ mod style_parser {
         .map(Color::Indexed)
     };
     let named_color = || {
-      many1(letter()).and_then(|x: String| match x.as_str() {
-        "reset" => Ok(Color::Reset),
-        "black" => Ok(Color::Black),
-        "red" => Ok(Color::Red),
-        "green" => Ok(Color::Green),
-        "yellow" => Ok(Color::Yellow),
-        "blue" => Ok(Color::Blue),
-        "magenta" => Ok(Color::Magenta),
-        "cyan" => Ok(Color::Cyan),
-        "gray" => Ok(Color::Gray),
-        "darkgray" => Ok(Color::DarkGray),
-        "lightred" => Ok(Color::LightRed),
-        "lightgreen" => Ok(Color::LightGreen),
-        "lightyellow" => Ok(Color::LightYellow),
-        "lightblue" => Ok(Color::LightBlue),
-        "lightmagenta" => Ok(Color::LightMagenta),
-        "lightcyan" => Ok(Color::LightCyan),
-        "white" => Ok(Color::White),
-        &_ => Err(error::UnexpectedParse::Unexpected),
+      many1(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_')).and_then(move |x: String| {
+        named(&x)
+          .or_else(|| palette.get(&x).copied())
+          .ok_or(error::UnexpectedParse::Unexpected)
       })
     };
     choice!(attempt(rgb()), attempt(indexed()), attempt(named_color()))
   }
 
+  /// The built-in color names
+  pub fn named(name: &str) -> Option<Color> {
+    Some(match name {
+      "reset" => Color::Reset,
+      "black" => Color::Black,
+      "red" => Color::Red,
+      "green" => Color::Green,
+      "yellow" => Color::Yellow,
+      "blue" => Color::Blue,
+      "magenta" => Color::Magenta,
+      "cyan" => Color::Cyan,
+      "gray" => Color::Gray,
+      "darkgray" => Color::DarkGray,
+      "lightred" => Color::LightRed,
+      "lightgreen" => Color::LightGreen,
+      "lightyellow" => Color::LightYellow,
+      "lightblue" => Color::LightBlue,
+      "lightmagenta" => Color::LightMagenta,
+      "lightcyan" => Color::LightCyan,
+      "white" => Color::White,
+      _ => return None,
+    })
+  }
+
   fn modifier<'a>() -> impl EasyParser<&'a str, Output = Modifier> {
     satisfy_map(|x: char| match x {
       'b' => Some(Modifier::BOLD),
@@ -228,9 +725,9 @@ mod style_parser {
     })
   }
 
-  pub fn style<'a>() -> impl EasyParser<&'a str, Output = Style> {
-    optional(color())
-      .and(optional(attempt(token(',').with(color()))))
+  pub fn style(palette: &HashMap<String, Color>) -> impl EasyParser<&str, Output = Style> {
+    optional(color(palette))
+      .and(optional(attempt(token(',').with(color(palette)))))
       .and(optional(attempt(token('+').with(modifiers()))))
       .and(optional(attempt(token('-').with(modifiers()))))
       .map(|(((fg, bg), add_mods), sub_mods)| {
@@ -252,16 +749,28 @@ mod style_parser {
   }
 }
 
+/// Parse a style with only the built-in color names
 pub fn parse_style(input: &str) -> Result<Style, String> {
-  match style_parser::style().easy_parse(input) {
+  parse_style_with(input, &HashMap::new())
+}
+
+fn parse_style_with(input: &str, palette: &HashMap<String, Color>) -> Result<Style, String> {
+  use combine::Parser;
+  match style_parser::style(palette)
+    .skip(combine::eof())
+    .easy_parse(input)
+  {
     Err(e) => Err(format!("error parsing style: {}", e)),
     Ok((style, _)) => Ok(style),
   }
 }
 
-#[allow(dead_code)]
-pub fn parse_color(input: &str) -> Result<Color, String> {
-  match style_parser::color().easy_parse(input) {
+fn parse_color_with(input: &str, palette: &HashMap<String, Color>) -> Result<Color, String> {
+  use combine::Parser;
+  match style_parser::color(palette)
+    .skip(combine::eof())
+    .easy_parse(input)
+  {
     Err(e) => Err(format!("error parsing color: {}", e)),
     Ok((color, _)) => Ok(color),
   }
@@ -276,7 +785,7 @@ mod tests {
 
   #[test]
   fn style_parsing() {
-    assert!(parse_color(",").is_err());
+    assert!(Config::default().parse_color(",").is_err());
     assert_eq!(parse_style(""), Ok(Style::default()));
     assert_eq!(parse_style("blue"), Ok(Style::default().fg(Blue)));
     assert_eq!(
@@ -328,4 +837,240 @@ mod tests {
       "color1,rgb:0011FF+b-iu"
     );
   }
+
+  #[test]
+  fn defaults_match_default_config() {
+    use crate::commands::{parse_cmds, Command};
+    let defaults = Config::default();
+    let mut config = Config::default();
+    for cmd in parse_cmds(crate::config_layers::DEFAULT_CONFIG).unwrap() {
+      if let Command::Set(_, name, vals) = cmd {
+        config.set_values(&name, &vals).unwrap();
+        assert_eq!(config.get_opt(&name), defaults.get_opt(&name), "{}", name);
+      }
+    }
+    let opt = defaults.option("message_timeout").unwrap();
+    assert_eq!((opt.type_name, opt.default.as_str()), ("i32", "5000"));
+    assert!(opt.doc.starts_with("Milliseconds"));
+  }
+
+  /// The `Options` section of the README, generated from the option docs
+  fn options_readme() -> String {
+    let mut res = String::new();
+    for opt in Config::default().options() {
+      let type_name = match opt.values.is_empty() {
+        true => opt.type_name.to_string(),
+        false => opt.values.join("|"),
+      };
+      res.push_str(&format!(
+        "### `{}: {}`\n{}\n\n",
+        opt.name, type_name, opt.doc
+      ));
+      match opt.default.as_str() {
+        "" => res.push_str("Default: empty"),
+        default => res.push_str(&format!("Default: `{}`", default)),
+      }
+      if let Some(range) = opt.range {
+        res.push_str(&format!(", range: `{}`", range));
+      }
+      res.push_str("\n\n");
+    }
+    res
+  }
+
+  /// Fails if the README is out of date. Run with `UPDATE_README=1` to update it
+  #[test]
+  fn readme_documents_options() {
+    const BEGIN: &str = "<!-- begin generated options -->\n";
+    const END: &str = "<!-- end generated options -->";
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("README.md");
+    let readme = std::fs::read_to_string(&path).unwrap();
+    let start = readme.find(BEGIN).unwrap() + BEGIN.len();
+    let end = readme.find(END).unwrap();
+    let expected = options_readme();
+    if std::env::var_os("UPDATE_README").is_some() {
+      let updated = format!("{}{}{}", &readme[..start], expected, &readme[end..]);
+      std::fs::write(&path, updated).unwrap();
+    } else {
+      assert!(
+        readme[start..end] == expected,
+        "README options are out of date, run `UPDATE_README=1 cargo test`"
+      );
+    }
+  }
+
+  #[derive(ConfParsable)]
+  struct TestConfig {
+    #[conf(default = "3", range = "1..=5")]
+    level: u16,
+    patterns: Vec<String>,
+    sorts: Vec<SortBy>,
+    limit: Option<usize>,
+  }
+
+  #[test]
+  fn option_types() {
+    let mut conf = TestConfig::default();
+    assert_eq!(conf.level, 3);
+    assert!(conf.get_child_mut("level").unwrap().set_opt("9").is_ok());
+    assert!(conf.check_opt("level").is_err());
+
+    let patterns = conf.get_child_mut("patterns").unwrap();
+    patterns
+      .set_values(&["a".to_string(), "b".to_string()])
+      .unwrap();
+    patterns.add_opt("c").unwrap();
+    patterns.remove_opt("a").unwrap();
+    assert_eq!(patterns.get_opt(), "b c");
+    patterns.add_opt("d e").unwrap();
+    assert_eq!(patterns.get_opt(), "b c 'd e'");
+    assert!(patterns.unset_opt().is_err());
+
+    let sorts = conf.get_child_mut("sorts").unwrap();
+    assert!(sorts.add_opt("mtime").is_ok());
+    assert!(sorts.add_opt("date").is_err());
+    assert_eq!(sorts.values(), vec!["name", "extension", "size", "mtime"]);
+    assert_eq!(conf.sorts, vec![SortBy::Mtime]);
+
+    let limit = conf.get_child_mut("limit").unwrap();
+    assert_eq!(limit.get_opt(), "");
+    limit.set_opt("10").unwrap();
+    assert!(limit.set_opt("-1").is_err());
+    assert_eq!(conf.limit, Some(10));
+    conf.get_child_mut("limit").unwrap().unset_opt().unwrap();
+    assert_eq!(conf.limit, None);
+  }
+
+  #[test]
+  fn palette_colors() {
+    let mut config = Config::default();
+    let set = |c: &mut Config, v: &str| c.set_values("style.dir", &[v.to_string()]);
+    assert!(set(&mut config, "accent").is_err());
+    config.set_color("accent", "rgb:88C0D0").unwrap();
+    config.set_color("alias", "accent").unwrap();
+    set(&mut config, "alias,red+b").unwrap();
+    assert_eq!(
+      config.style.dir,
+      Style::default()
+        .fg(Rgb(0x88, 0xC0, 0xD0))
+        .bg(Red)
+        .add_modifier(Modifier::BOLD)
+    );
+    config.set_color("alias", "blue").unwrap();
+    assert_eq!(config.style.dir.fg, Some(Blue));
+    assert_eq!(config.parse_color("accent"), Ok(Rgb(0x88, 0xC0, 0xD0)));
+    assert!(parse_style("accent").is_err());
+    config.reset_opt("dir_name_style").unwrap();
+    config.set_color("alias", "green").unwrap();
+    assert_eq!(config.get_opt("style.dir"), Ok("lightblue+b".to_string()));
+    assert!(Config::default().parse_color("accent").is_err());
+
+    assert!(config.set_color("red", "blue").is_err());
+    assert!(config.set_color("color1", "blue").is_err());
+    assert!(config.set_color("a-b", "blue").is_err());
+    assert!(config.set_color("bad", "rgb:88").is_err());
+
+    set(&mut config, "alias").unwrap();
+    config
+      .palette
+      .styles
+      .insert("style.file".to_string(), "missing".to_string());
+    assert!(config.set_color("alias", "red").is_err());
+    assert!(config.set_color("new", "red").is_err());
+    assert_eq!(config.parse_color("alias"), Ok(Green));
+    assert!(config.parse_color("new").is_err());
+    assert_eq!(config.style.dir.fg, Some(Green));
+  }
+
+  #[test]
+  fn range_is_checked() {
+    let mut config = Config::default();
+    let set = |c: &mut Config, v: &str| c.set_values("indent_width", &[v.to_string()]);
+    assert!(set(&mut config, "4").is_ok());
+    assert_eq!(
+      set(&mut config, "9"),
+      Err("indent_width must be in 0..=8".to_string())
+    );
+    assert_eq!(config.indent_width, 4);
+    assert!(config.add_opt("show_hidden", "true").is_err());
+    assert!(config.inc_opt("indent_width", "5").is_err());
+    assert_eq!(config.indent_width, 4);
+    config.inc_opt("indent_width", "-1").unwrap();
+    assert_eq!(config.indent_width, 3);
+    assert!(config.inc_opt("indent_width", "-4").is_err());
+    assert!(config.inc_opt("show_hidden", "1").is_err());
+  }
+
+  #[test]
+  fn toggle_and_reset() {
+    let mut config = Config::default();
+    config.toggle_opt("show_hidden").unwrap();
+    assert!(config.show_hidden);
+    assert_eq!(
+      config.toggle_opt("open_cmd"),
+      Err("open_cmd: not a boolean".to_string())
+    );
+    config
+      .set_values("open_cmd", &["less".to_string()])
+      .unwrap();
+    config
+      .set_values("link_style", &["red".to_string()])
+      .unwrap();
+    config.reset_opt("open_cmd").unwrap();
+    config.unset_opt("link_style").unwrap();
+    assert_eq!(config.open_cmd, "xdg-open");
+    assert_eq!(config.get_opt("style.link"), Ok("cyan+b".to_string()));
+    assert!(config.reset_opt("style.foo").is_err());
+
+    let mut conf = TestConfig::default();
+    conf.get_child_mut("level").unwrap().set_opt("5").unwrap();
+    conf.reset_opt("level").unwrap();
+    assert_eq!(conf.level, 3);
+  }
+
+  #[test]
+  fn hide_patterns_are_compiled_when_set() {
+    let mut config = Config::default();
+    let patterns = ["*.o".to_string(), "build/*".to_string()];
+    config.set_values("hide_patterns", &patterns).unwrap();
+    assert!(config.is_hidden_file(Path::new("/a/main.o")));
+    assert!(config.is_hidden_file(Path::new("build/x")));
+    assert!(!config.is_hidden_file(Path::new("/a/build/x")));
+    assert!(config.add_opt("hide_patterns", "[a").is_err());
+    assert_eq!(
+      config.get_opt("hide_patterns"),
+      Ok("*.o build/*".to_string())
+    );
+  }
+
+  #[test]
+  fn failed_changes_are_rolled_back() {
+    let mut config = Config::default();
+    let patterns = ["a b".to_string(), "c".to_string()];
+    config.set_values("hide_patterns", &patterns).unwrap();
+    let res = config.modify_opt("hide_patterns", |opt| {
+      opt.add_opt("d")?;
+      Err("failed".to_string())
+    });
+    assert_eq!(res, Err("hide_patterns: failed".to_string()));
+    let patterns: Vec<_> = config.hide_patterns.iter().map(|p| p.as_str()).collect();
+    assert_eq!(patterns, ["a b", "c"]);
+  }
+
+  #[test]
+  fn nested_options() {
+    let mut config = Config::default();
+    assert_eq!(config.get_opt("style.dir"), Ok("lightblue+b".to_string()));
+    config
+      .set_values("dir_name_style", &["red".to_string()])
+      .unwrap();
+    assert_eq!(config.style.dir, Style::default().fg(Red));
+    assert_eq!(
+      config.get_opt("style.foo"),
+      Err("unknown option style.foo".to_string())
+    );
+    assert!(config.get_opt("style").is_err());
+    assert!(config.options().iter().any(|o| o.name == "style.error"));
+    assert_eq!(config.option("error_style").unwrap().name, "style.error");
+  }
 }
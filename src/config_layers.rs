@@ -0,0 +1,259 @@
+use crate::commands::{Command, Script, ScriptError};
+use crate::config::is_style_opt;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Built-in defaults, loaded before any config file
+pub const DEFAULT_CONFIG: &str = include_str!("../sidetreerc");
+
+/// Themes shipped with sidetree, by name
+pub const BUNDLED_THEMES: &[(&str, &str)] = &[
+  ("gruvbox-dark", include_str!("../themes/gruvbox-dark")),
+  ("gruvbox-light", include_str!("../themes/gruvbox-light")),
+  ("nord", include_str!("../themes/nord")),
+];
+
+/// Name of project-local config files
+pub const PROJECT_CONFIG_NAME: &str = ".sidetreerc";
+
+fn xdg() -> xdg::BaseDirectories {
+  xdg::BaseDirectories::with_prefix("sidetree").unwrap()
+}
+
+/// Written to the user config file when it does not exist yet. The defaults
+/// are loaded anyway, so it only points to them
+const USER_CONFIG_STUB: &str = "\
+# sidetree config, run after the built-in defaults.
+# See :help and :options for the available commands and options.
+#
+# set show_hidden true
+# map <c-r> reload-config
+";
+
+/// The user config file, `$XDG_CONFIG_HOME/sidetree/sidetreerc`.
+/// It is created with a commented stub if it does not exist
+pub fn default_user_config() -> PathBuf {
+  let conf_file = xdg()
+    .place_config_file("sidetreerc")
+    .expect("Cannot create config directory");
+  if !conf_file.exists() {
+    std::fs::write(&conf_file, USER_CONFIG_STUB).expect("Couldn't write default config file");
+  }
+  conf_file
+}
+
+/// System wide config files from `$XDG_CONFIG_DIRS`, i.e.
+/// `/etc/xdg/sidetree/sidetreerc`, least preferred first
+pub fn system_configs() -> Vec<PathBuf> {
+  let mut res: Vec<PathBuf> = xdg()
+    .get_config_dirs()
+    .into_iter()
+    .map(|d| d.join("sidetreerc"))
+    .filter(|f| f.is_file())
+    .collect();
+  res.reverse();
+  res
+}
+
+/// The closest `.sidetreerc` in `dir` or its ancestors
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+  dir
+    .ancestors()
+    .map(|d| d.join(PROJECT_CONFIG_NAME))
+    .find(|f| f.is_file())
+}
+
+/// A theme file `$XDG_CONFIG_HOME/sidetree/themes/<name>`, or from
+/// `$XDG_CONFIG_DIRS`
+pub fn find_theme(name: &str) -> Option<PathBuf> {
+  xdg().find_config_file(Path::new("themes").join(name))
+}
+
+/// Keep only the commands a theme may run: `set` on the `style` options and
+/// `set-color`. Other commands are reported as errors instead of running
+pub fn theme_script(mut script: Script) -> Script {
+  let (cmds, rejected): (Vec<_>, Vec<_>) =
+    script.cmds.into_iter().partition(|(_, cmd)| match cmd {
+      Command::Set(_, name, _) => is_style_opt(name),
+      Command::SetColor(..) => true,
+      _ => false,
+    });
+  script.cmds = cmds;
+  script
+    .errors
+    .extend(rejected.into_iter().map(|(pos, _)| ScriptError {
+      pos,
+      msg: "themes can only set style options and colors".to_string(),
+    }));
+  script
+}
+
+/// Names of the bundled and installed themes, sorted
+pub fn theme_names() -> Vec<String> {
+  let mut res: Vec<String> = BUNDLED_THEMES.iter().map(|(n, _)| n.to_string()).collect();
+  res.extend(
+    xdg()
+      .list_config_files("themes")
+      .iter()
+      .filter_map(|f| f.file_name())
+      .map(|n| n.to_string_lossy().into_owned()),
+  );
+  res.push("default".to_string());
+  res.sort();
+  res.dedup();
+  res
+}
+
+fn trust_file() -> Option<PathBuf> {
+  xdg().place_data_file("trusted").ok()
+}
+
+/// Whether a project config may be run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustState {
+  Trusted,
+  /// The config was trusted, but modified since
+  Changed,
+  Untrusted,
+}
+
+/// Hex encoded SHA-256 of the contents of a config file
+fn content_hash(path: &Path) -> Option<String> {
+  let contents = std::fs::read(path).ok()?;
+  Some(
+    Sha256::digest(contents)
+      .iter()
+      .map(|b| format!("{:02x}", b))
+      .collect(),
+  )
+}
+
+/// The trust state of `path` with contents `hash`, from a list of trusted
+/// configs with one `<hash> <path>` line per config
+fn trust_state_in(list: &str, path: &Path, hash: &str) -> TrustState {
+  let mut res = TrustState::Untrusted;
+  for (trusted_hash, trusted_path) in list.lines().filter_map(|l| l.split_once(' ')) {
+    if Path::new(trusted_path) == path {
+      if trusted_hash == hash {
+        return TrustState::Trusted;
+      }
+      res = TrustState::Changed;
+    }
+  }
+  res
+}
+
+/// Whether a project config was marked as trusted with the `trust` command,
+/// and not modified since
+pub fn trust_state(path: &Path) -> TrustState {
+  let list = trust_file()
+    .and_then(|f| std::fs::read_to_string(f).ok())
+    .unwrap_or_default();
+  match content_hash(path) {
+    Some(hash) => trust_state_in(&list, path, &hash),
+    None => TrustState::Untrusted,
+  }
+}
+
+/// Add a project config with its current contents to the list of trusted
+/// configs in the data dir, replacing an older entry for it
+pub fn trust(path: &Path) -> Result<(), String> {
+  let hash = content_hash(path).ok_or_else(|| format!("cannot read {}", path.display()))?;
+  let file = trust_file().ok_or("Cannot create data directory")?;
+  let list = std::fs::read_to_string(&file).unwrap_or_default();
+  let mut list: String = list
+    .lines()
+    .filter(|l| !matches!(l.split_once(' '), Some((_, p)) if Path::new(p) == path))
+    .map(|l| format!("{}\n", l))
+    .collect();
+  list.push_str(&format!("{} {}\n", hash, path.to_string_lossy()));
+  std::fs::write(&file, list).map_err(|e| e.to_string())
+}
+
+/// Expand a leading `~` and `$VAR` or `${VAR}` environment variables in a path
+pub fn expand_path(path: &str) -> PathBuf {
+  let mut res = String::new();
+  let mut rest = path;
+  if rest == "~" || rest.starts_with("~/") {
+    res.push_str(&std::env::var("HOME").unwrap_or_default());
+    rest = &rest[1..];
+  }
+  while let Some(i) = rest.find('$') {
+    res.push_str(&rest[..i]);
+    rest = &rest[i + 1..];
+    let (name, tail) = if let Some(braced) = rest.strip_prefix('{') {
+      match braced.find('}') {
+        Some(end) => (&braced[..end], &braced[end + 1..]),
+        None => ("", rest),
+      }
+    } else {
+      let end = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+      (&rest[..end], &rest[end..])
+    };
+    if name.is_empty() {
+      res.push('$');
+    } else {
+      res.push_str(&std::env::var(name).unwrap_or_default());
+    }
+    rest = tail;
+  }
+  res.push_str(rest);
+  PathBuf::from(res)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::commands::parse_script;
+  use crate::config_layers::*;
+
+  #[test]
+  fn path_expansion() {
+    std::env::set_var("SIDETREE_TEST_DIR", "/tmp/x");
+    let home = std::env::var("HOME").unwrap_or_default();
+    assert_eq!(expand_path("~/a"), PathBuf::from(format!("{}/a", home)));
+    assert_eq!(
+      expand_path("$SIDETREE_TEST_DIR/b"),
+      PathBuf::from("/tmp/x/b")
+    );
+    assert_eq!(
+      expand_path("${SIDETREE_TEST_DIR}c"),
+      PathBuf::from("/tmp/xc")
+    );
+    assert_eq!(expand_path("a$/~"), PathBuf::from("a$/~"));
+  }
+
+  #[test]
+  fn trust_requires_same_contents() {
+    let path = Path::new("/p/.sidetreerc");
+    let list = "abc /q/.sidetreerc\ndef /p/.sidetreerc\n";
+    assert_eq!(trust_state_in(list, path, "def"), TrustState::Trusted);
+    assert_eq!(trust_state_in(list, path, "abc"), TrustState::Changed);
+    assert_eq!(
+      trust_state_in(list, Path::new("/r"), "abc"),
+      TrustState::Untrusted
+    );
+    // Lists from before contents were hashed only contain paths
+    assert_eq!(
+      trust_state_in("/p/.sidetreerc\n", path, "def"),
+      TrustState::Untrusted
+    );
+    assert_eq!(parse_script(USER_CONFIG_STUB).cmds, vec![]);
+  }
+
+  #[test]
+  fn bundled_themes_parse() {
+    for (name, theme) in BUNDLED_THEMES {
+      let script = theme_script(parse_script(theme));
+      assert!(script.errors.is_empty(), "{}: {:?}", name, script.errors);
+      assert!(!script.cmds.is_empty());
+    }
+    let script = theme_script(parse_script(
+      "set style.dir red\nset-color a blue\nset link_style a\nshell rm x\nset open_cmd x",
+    ));
+    assert_eq!(script.cmds.len(), 3);
+    let lines: Vec<_> = script.errors.iter().map(|e| e.pos.line).collect();
+    assert_eq!(lines, vec![4, 5]);
+  }
+}
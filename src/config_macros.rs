@@ -2,7 +2,16 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::spanned::Spanned;
 
-#[proc_macro_derive(ConfParsable)]
+/// Derives `ConfTree` and `Default` for a config struct.
+///
+/// The doc comment of each field is its option documentation,
+/// `#[conf(default = "value")]` sets its default, in the syntax of `set`, and
+/// `#[conf(range = "1..=8")]` restricts the values of numeric options.
+/// Fields marked `#[conf(nested)]` are structs deriving `ConfParsable`
+/// themselves, and their options are named `field.option`.
+/// Fields marked `#[conf(skip)]` are not options, and start out as their
+/// `Default`.
+#[proc_macro_derive(ConfParsable, attributes(conf))]
 pub fn derive_conf_tree(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
   let ast = syn::parse_macro_input!(input as syn::DeriveInput);
 
@@ -11,6 +20,70 @@ pub fn derive_conf_tree(input: proc_macro::TokenStream) -> proc_macro::TokenStre
   toks.into()
 }
 
+/// Doc comment lines of a field, without the leading space
+fn doc_comment(field: &syn::Field) -> String {
+  field
+    .attrs
+    .iter()
+    .filter(|a| a.path.is_ident("doc"))
+    .filter_map(|a| match a.parse_meta() {
+      Ok(syn::Meta::NameValue(syn::MetaNameValue {
+        lit: syn::Lit::Str(s),
+        ..
+      })) => Some(s.value().trim().to_string()),
+      _ => None,
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Attributes of a field given with `#[conf(...)]`
+#[derive(Default)]
+struct ConfAttrs {
+  default: Option<syn::LitStr>,
+  range: Option<syn::LitStr>,
+  name: Option<syn::LitStr>,
+  nested: bool,
+  skip: bool,
+}
+
+fn conf_attrs(attrs: &[syn::Attribute]) -> syn::Result<ConfAttrs> {
+  let mut res = ConfAttrs::default();
+  for attr in attrs.iter().filter(|a| a.path.is_ident("conf")) {
+    let list = match attr.parse_meta()? {
+      syn::Meta::List(list) => list,
+      meta => return Err(syn::Error::new(meta.span(), "Expected #[conf(...)]")),
+    };
+    for nested in list.nested {
+      match nested {
+        syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("nested") => {
+          res.nested = true;
+        }
+        syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("skip") => {
+          res.skip = true;
+        }
+        syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+          path,
+          lit: syn::Lit::Str(s),
+          ..
+        })) => {
+          if path.is_ident("default") {
+            res.default = Some(s);
+          } else if path.is_ident("range") {
+            res.range = Some(s);
+          } else if path.is_ident("name") {
+            res.name = Some(s);
+          } else {
+            return Err(syn::Error::new(path.span(), "Unknown conf attribute"));
+          }
+        }
+        nested => return Err(syn::Error::new(nested.span(), "Unknown conf attribute")),
+      }
+    }
+  }
+  Ok(res)
+}
+
 fn conf_tree(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
   let name = &ast.ident;
   let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
@@ -21,36 +94,224 @@ fn conf_tree(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
     return Err(syn::Error::new(ast.span(), "Expected struct"));
   };
 
-  let field_names = fields
-    .iter()
-    .map(|f| {
-      f.ident
-        .as_ref()
-        .ok_or_else(|| syn::Error::new(f.span(), "Expected field name"))
-    })
-    .collect::<syn::Result<Vec<_>>>()?;
+  // Options, and nested structs of options
+  let mut leaves = vec![];
+  let mut field_names = vec![];
+  let mut nested_names = vec![];
+  let mut skipped_names = vec![];
+  for f in fields {
+    let ident = f
+      .ident
+      .as_ref()
+      .ok_or_else(|| syn::Error::new(f.span(), "Expected field name"))?;
+    let attrs = conf_attrs(&f.attrs)?;
+    if attrs.skip {
+      skipped_names.push(ident);
+    } else if attrs.nested {
+      nested_names.push(ident);
+    } else {
+      leaves.push(f);
+      field_names.push(ident);
+    }
+  }
 
   let field_strs = field_names
     .iter()
     .map(|ident| ident.to_string())
     .collect::<Vec<_>>();
+  let nested_strs = nested_names
+    .iter()
+    .map(|ident| ident.to_string())
+    .collect::<Vec<_>>();
+
+  let type_strs = leaves
+    .iter()
+    .map(|f| {
+      let ty = &f.ty;
+      quote!(#ty).to_string().replace(' ', "")
+    })
+    .collect::<Vec<_>>();
+
+  let docs = leaves.iter().copied().map(doc_comment).collect::<Vec<_>>();
+
+  let mut set_defaults = vec![];
+  let mut checks = vec![];
+  let mut ranges = vec![];
+  for (field, ident) in leaves.iter().zip(&field_names) {
+    let attrs = conf_attrs(&field.attrs)?;
+    if let Some(default) = attrs.default {
+      let msg = format!("invalid default for option {}", ident);
+      set_defaults.push(quote! {
+        crate::config::ConfOpt::set_opt(&mut res.#ident, #default).expect(#msg);
+      });
+    }
+    match attrs.range {
+      Some(range) => {
+        let range_str = range.value();
+        let range: syn::Expr = range.parse()?;
+        let msg = format!("{} must be in {}", ident, range_str);
+        let name = ident.to_string();
+        checks.push(quote! {
+          #name if !(#range).contains(&self.#ident) => Err(#msg.to_string()),
+        });
+        ranges.push(quote!(Some(#range_str)));
+      }
+      None => ranges.push(quote!(None)),
+    }
+  }
 
   Ok(quote! {
     impl #impl_generics crate::config::ConfTree for #name #ty_generics #where_clause {
 
       fn get_child(&self, name: &str) -> Result<&dyn crate::config::ConfOpt, String> {
-        match name {
-          #(#field_strs => Ok(&self.#field_names),)*
-          _ => Err(format!("unknown option {}", name)),
+        match name.split_once('.') {
+          #(Some((#nested_strs, rest)) => crate::config::ConfTree::get_child(&self.#nested_names, rest)
+            .map_err(|_| format!("unknown option {}", name)),)*
+          _ => match name {
+            #(#field_strs => Ok(&self.#field_names),)*
+            _ => Err(format!("unknown option {}", name)),
+          },
         }
       }
 
       fn get_child_mut(&mut self, name: &str) -> Result<&mut dyn crate::config::ConfOpt, String> {
-        match name {
-          #(#field_strs => Ok(&mut self.#field_names),)*
-          _ => Err(format!("unknown option {}", name)),
+        match name.split_once('.') {
+          #(Some((#nested_strs, rest)) => crate::config::ConfTree::get_child_mut(&mut self.#nested_names, rest)
+            .map_err(|_| format!("unknown option {}", name)),)*
+          _ => match name {
+            #(#field_strs => Ok(&mut self.#field_names),)*
+            _ => Err(format!("unknown option {}", name)),
+          },
+        }
+      }
+
+      fn options(&self) -> Vec<crate::config::OptInfo> {
+        let defaults = <Self as Default>::default();
+        #[allow(unused_mut)]
+        let mut res = vec![
+          #(crate::config::OptInfo {
+            name: #field_strs.to_string(),
+            type_name: #type_strs,
+            doc: #docs,
+            default: crate::config::ConfOpt::get_opt(&defaults.#field_names),
+            range: #ranges,
+            values: crate::config::ConfOpt::values(&defaults.#field_names),
+          },)*
+        ];
+        #(for mut opt in crate::config::ConfTree::options(&self.#nested_names) {
+          opt.name = format!("{}.{}", #nested_strs, opt.name);
+          res.push(opt);
+        })*
+        res
+      }
+
+      fn reset_opt(&mut self, name: &str) -> Result<(), String> {
+        #[allow(unused_variables)]
+        let defaults = <Self as Default>::default();
+        match name.split_once('.') {
+          #(Some((#nested_strs, rest)) => crate::config::ConfTree::reset_opt(&mut self.#nested_names, rest)
+            .map_err(|_| format!("unknown option {}", name)),)*
+          _ => match name {
+            #(#field_strs => {
+              self.#field_names = defaults.#field_names;
+              Ok(())
+            })*
+            _ => Err(format!("unknown option {}", name)),
+          },
+        }
+      }
+
+      fn check_opt(&self, name: &str) -> Result<(), String> {
+        match name.split_once('.') {
+          #(Some((#nested_strs, rest)) => crate::config::ConfTree::check_opt(&self.#nested_names, rest),)*
+          _ => match name {
+            #(#checks)*
+            _ => Ok(()),
+          },
         }
       }
     }
+
+    impl #impl_generics Default for #name #ty_generics #where_clause {
+      fn default() -> Self {
+        let mut res = Self {
+          #(#field_names: Default::default(),)*
+          #(#nested_names: Default::default(),)*
+          #(#skipped_names: Default::default(),)*
+        };
+        #(#set_defaults)*
+        res
+      }
+    }
+  })
+}
+
+/// Derives `ConfOpt` for an enum without fields, so it can be used as an
+/// option type. Variants are named in snake_case, or by `#[conf(name = "...")]`
+#[proc_macro_derive(ConfEnum, attributes(conf))]
+pub fn derive_conf_enum(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+  let ast = syn::parse_macro_input!(input as syn::DeriveInput);
+
+  let toks = conf_enum(&ast).unwrap_or_else(|err| err.to_compile_error());
+  toks.into()
+}
+
+fn snake_case(ident: &syn::Ident) -> String {
+  let mut res = String::new();
+  for (i, c) in ident.to_string().chars().enumerate() {
+    if c.is_uppercase() && i > 0 {
+      res.push('_');
+    }
+    res.extend(c.to_lowercase());
+  }
+  res
+}
+
+fn conf_enum(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+  let name = &ast.ident;
+  let variants = if let syn::Data::Enum(ref data) = ast.data {
+    &data.variants
+  } else {
+    return Err(syn::Error::new(ast.span(), "Expected enum"));
+  };
+
+  let mut idents = vec![];
+  let mut strs = vec![];
+  for v in variants {
+    if !v.fields.is_empty() {
+      return Err(syn::Error::new(v.span(), "Expected variant without fields"));
+    }
+    let attrs = conf_attrs(&v.attrs)?;
+    idents.push(&v.ident);
+    strs.push(
+      attrs
+        .name
+        .map(|n| n.value())
+        .unwrap_or_else(|| snake_case(&v.ident)),
+    );
+  }
+  let expected = format!("expected one of {}", strs.join(", "));
+
+  Ok(quote! {
+    impl crate::config::ConfOpt for #name {
+      fn set_opt(&mut self, val: &str) -> Result<(), String> {
+        *self = match val {
+          #(#strs => #name::#idents,)*
+          _ => return Err(#expected.to_string()),
+        };
+        Ok(())
+      }
+
+      fn get_opt(&self) -> String {
+        match self {
+          #(#name::#idents => #strs,)*
+        }
+        .to_string()
+      }
+
+      fn values(&self) -> Vec<&'static str> {
+        vec![#(#strs),*]
+      }
+    }
   })
 }
@@ -0,0 +1,307 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error of a file operation triggered by a command
+#[derive(Debug)]
+pub enum FileOpError {
+  /// The destination of the operation already exists
+  Exists(PathBuf),
+  /// The given file name is empty, `.`, `..` or contains a path separator
+  InvalidName(String),
+  /// The operation would remove the tree root
+  IsRoot(PathBuf),
+  /// Several entries would be renamed to the same path
+  Conflict(PathBuf),
+  /// The underlying `std::fs` call failed
+  Io {
+    op: &'static str,
+    path: PathBuf,
+    err: io::Error,
+  },
+}
+
+impl fmt::Display for FileOpError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FileOpError::Exists(path) => write!(f, "{} already exists", path.display()),
+      FileOpError::InvalidName(name) => write!(f, "invalid file name '{}'", name),
+      FileOpError::IsRoot(path) => write!(f, "refusing to delete root {}", path.display()),
+      FileOpError::Conflict(path) => {
+        write!(f, "several entries would be renamed to {}", path.display())
+      }
+      FileOpError::Io { op, path, err } => {
+        write!(f, "could not {} {}: {}", op, path.display(), err)
+      }
+    }
+  }
+}
+
+impl std::error::Error for FileOpError {}
+
+pub type Result<T> = std::result::Result<T, FileOpError>;
+
+fn io_err(op: &'static str, path: &Path) -> impl FnOnce(io::Error) -> FileOpError {
+  let path = path.to_path_buf();
+  move |err| FileOpError::Io { op, path, err }
+}
+
+fn check_absent(path: &Path) -> Result<()> {
+  // symlink_metadata so dangling links count as existing
+  if path.symlink_metadata().is_ok() {
+    Err(FileOpError::Exists(path.to_path_buf()))
+  } else {
+    Ok(())
+  }
+}
+
+/// Check that `name` is a single path component, so renaming an entry to it
+/// keeps the entry in its directory
+pub fn check_name(name: &str) -> Result<()> {
+  if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+    Err(FileOpError::InvalidName(name.to_string()))
+  } else {
+    Ok(())
+  }
+}
+
+/// Rename `src` to `name`, keeping it in the same directory.
+/// Returns the new path
+pub fn rename(src: &Path, name: &str) -> Result<PathBuf> {
+  check_name(name)?;
+  let dst = src.with_file_name(name);
+  if dst == src {
+    return Ok(dst);
+  }
+  check_absent(&dst)?;
+  std::fs::rename(src, &dst).map_err(io_err("rename", src))?;
+  Ok(dst)
+}
+
+/// Create an empty file `name` in `dir`. Intermediate directories are created,
+/// and a trailing `/` creates a directory instead.
+pub fn new_file(dir: &Path, name: &str) -> Result<PathBuf> {
+  if name.ends_with('/') {
+    return new_dir(dir, name);
+  }
+  if name.is_empty() {
+    return Err(FileOpError::InvalidName(name.to_string()));
+  }
+  let path = dir.join(name);
+  check_absent(&path)?;
+  if let Some(parent) = path.parent() {
+    std::fs::create_dir_all(parent).map_err(io_err("create", parent))?;
+  }
+  std::fs::OpenOptions::new()
+    .write(true)
+    .create_new(true)
+    .open(&path)
+    .map_err(io_err("create", &path))?;
+  Ok(path)
+}
+
+/// Create the directory `name` in `dir`, including intermediate directories.
+pub fn new_dir(dir: &Path, name: &str) -> Result<PathBuf> {
+  if name.trim_end_matches('/').is_empty() {
+    return Err(FileOpError::InvalidName(name.to_string()));
+  }
+  let path = dir.join(name);
+  check_absent(&path)?;
+  std::fs::create_dir_all(&path).map_err(io_err("create", &path))?;
+  Ok(path)
+}
+
+/// Result of `rename_all`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RenameSummary {
+  pub renamed: usize,
+  /// Number of cycles like `a → b, b → a` that were broken with a temporary name
+  pub cycles: usize,
+}
+
+/// The targets of `renames` that cannot be applied, with the reason: invalid
+/// names, targets of several renames, and existing entries that are not renamed
+/// away themselves. Renames to the same path are ignored.
+fn target_errors(renames: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, FileOpError)> {
+  let renames: Vec<_> = renames.iter().filter(|(s, d)| s != d).collect();
+  let sources: HashSet<&PathBuf> = renames.iter().map(|(s, _)| s).collect();
+  let mut counts: HashMap<&PathBuf, usize> = HashMap::new();
+  for (_, dst) in &renames {
+    *counts.entry(dst).or_default() += 1;
+  }
+  renames
+    .iter()
+    .filter_map(|(_, dst)| {
+      let err = if dst.file_name().is_none() {
+        FileOpError::InvalidName(dst.to_string_lossy().into())
+      } else if counts[dst] > 1 {
+        FileOpError::Conflict(dst.clone())
+      } else if !sources.contains(dst) {
+        check_absent(dst).err()?
+      } else {
+        return None;
+      };
+      Some((dst.clone(), err))
+    })
+    .collect()
+}
+
+/// Check that a set of renames can be applied without overwriting anything.
+/// Renames to the same path are dropped.
+pub fn check_renames(renames: &[(PathBuf, PathBuf)]) -> Result<Vec<(PathBuf, PathBuf)>> {
+  if let Some((_, err)) = target_errors(renames).into_iter().next() {
+    return Err(err);
+  }
+  Ok(renames.iter().filter(|(s, d)| s != d).cloned().collect())
+}
+
+/// Targets of `renames` that cannot be applied, see `check_renames`
+pub fn conflicting_targets(renames: &[(PathBuf, PathBuf)]) -> HashSet<PathBuf> {
+  target_errors(renames)
+    .into_iter()
+    .map(|(dst, _)| dst)
+    .collect()
+}
+
+/// Renames of `paths` to the names at the same index in `names`.
+/// Entries stay in their directories, so names must be single path components
+pub fn renames_to_names(paths: &[PathBuf], names: &[&str]) -> Result<Vec<(PathBuf, PathBuf)>> {
+  paths
+    .iter()
+    .zip(names)
+    .map(|(src, name)| {
+      check_name(name)?;
+      Ok((src.clone(), src.with_file_name(name)))
+    })
+    .collect()
+}
+
+/// Apply several renames at once.
+/// Renames are ordered so no entry is overwritten, and cycles are broken by
+/// moving one entry to a temporary name first.
+pub fn rename_all(renames: &[(PathBuf, PathBuf)]) -> Result<RenameSummary> {
+  let mut pending = check_renames(renames)?;
+  let mut summary = RenameSummary::default();
+  while !pending.is_empty() {
+    let ready = pending
+      .iter()
+      .position(|(_, dst)| !pending.iter().any(|(src, _)| src == dst));
+    match ready {
+      Some(i) => {
+        let (src, dst) = pending.remove(i);
+        std::fs::rename(&src, &dst).map_err(io_err("rename", &src))?;
+        summary.renamed += 1;
+      }
+      None => {
+        // Every remaining target is the source of another rename: a cycle
+        let (src, _) = &pending[0];
+        let mut tmp_name = src.file_name().unwrap_or_default().to_owned();
+        tmp_name.push(format!(".sidetree-{}.tmp", std::process::id()));
+        let tmp = src.with_file_name(tmp_name);
+        check_absent(&tmp)?;
+        std::fs::rename(src, &tmp).map_err(io_err("rename", src))?;
+        pending[0].0 = tmp;
+        summary.cycles += 1;
+      }
+    }
+  }
+  Ok(summary)
+}
+
+/// Delete `path`, recursively if it is a directory.
+/// Symlinks are removed, not followed.
+pub fn delete(path: &Path) -> Result<()> {
+  let md = path.symlink_metadata().map_err(io_err("delete", path))?;
+  if md.is_dir() {
+    std::fs::remove_dir_all(path).map_err(io_err("delete", path))
+  } else {
+    std::fs::remove_file(path).map_err(io_err("delete", path))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::file_ops::*;
+  use crate::util::TempDir;
+
+  #[test]
+  fn conflicts_are_reported() {
+    let dir = TempDir::new("sidetree-file-ops-");
+    let a = new_file(&dir, "a").unwrap();
+    new_file(&dir, "b").unwrap();
+    assert!(matches!(new_file(&dir, "a"), Err(FileOpError::Exists(_))));
+    assert!(matches!(rename(&a, "b"), Err(FileOpError::Exists(_))));
+    assert!(matches!(
+      rename(&a, "x/y"),
+      Err(FileOpError::InvalidName(_))
+    ));
+    let c = rename(&a, "c").unwrap();
+    assert_eq!(c, dir.join("c"));
+    let sub = new_file(&dir, "sub/").unwrap();
+    assert!(sub.is_dir());
+    delete(&sub).unwrap();
+    assert!(!sub.exists());
+  }
+
+  #[test]
+  fn renamed_entries_stay_in_their_directory() {
+    let paths = [PathBuf::from("/a/x"), PathBuf::from("/a/b/y")];
+    assert_eq!(
+      renames_to_names(&paths, &["z", "y.txt"]).unwrap(),
+      vec![
+        (paths[0].clone(), PathBuf::from("/a/z")),
+        (paths[1].clone(), PathBuf::from("/a/b/y.txt")),
+      ]
+    );
+    for name in ["", ".", "..", "../z", "/etc/z", "b/z"] {
+      assert!(matches!(
+        renames_to_names(&paths, &["z", name]),
+        Err(FileOpError::InvalidName(_))
+      ));
+    }
+  }
+
+  #[test]
+  fn rename_all_with_cycles() {
+    let dir = TempDir::new("sidetree-rename-all-");
+    for name in ["a", "b", "c"] {
+      std::fs::write(dir.join(name), name).unwrap();
+    }
+    let p = |n: &str| dir.join(n);
+    assert!(matches!(
+      rename_all(&[(p("a"), p("x")), (p("b"), p("x"))]),
+      Err(FileOpError::Conflict(_))
+    ));
+    assert!(matches!(
+      rename_all(&[(p("a"), p("c"))]),
+      Err(FileOpError::Exists(_))
+    ));
+    let renames = [(p("a"), p("x")), (p("b"), p("y")), (p("c"), p("x"))];
+    assert_eq!(conflicting_targets(&renames), HashSet::from([p("x")]));
+    assert_eq!(
+      target_errors(&renames)
+        .iter()
+        .map(|(dst, _)| dst.clone())
+        .collect::<Vec<_>>(),
+      vec![p("x"), p("x")]
+    );
+    assert_eq!(
+      conflicting_targets(&[(p("a"), p("b")), (p("c"), p("c"))]),
+      HashSet::from([p("b")])
+    );
+    assert!(conflicting_targets(&[(p("a"), p("b")), (p("b"), p("a"))]).is_empty());
+    // a -> b -> c -> a
+    let summary = rename_all(&[(p("a"), p("b")), (p("b"), p("c")), (p("c"), p("a"))]).unwrap();
+    assert_eq!(
+      summary,
+      RenameSummary {
+        renamed: 3,
+        cycles: 1
+      }
+    );
+    assert_eq!(std::fs::read_to_string(p("b")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(p("c")).unwrap(), "b");
+    assert_eq!(std::fs::read_to_string(p("a")).unwrap(), "c");
+  }
+}
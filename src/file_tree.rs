@@ -1,11 +1,16 @@
-use crate::config::Config;
+use crate::config::{Config, SortBy};
 use crate::icons;
+use crate::mime;
+use crate::style_rules::{EntryInfo, StyleRules, LS_COLORS};
 use crate::util::StatefulList;
 use path_absolutize::Absolutize;
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashSet};
 use std::iter;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::SystemTime;
 use tui::{
   buffer::Buffer, layout::Rect, style::Style, text::Span, text::Spans, widgets::List,
   widgets::ListItem, widgets::StatefulWidget,
@@ -35,15 +40,21 @@ impl ExpandedPaths {
     self.expanded_paths.insert(PathBuf::from(path));
   }
 
-  #[allow(dead_code)]
   pub fn is_expanded(&self, path: &Path) -> bool {
     self.expanded_paths.contains(path)
   }
+
+  pub fn iter(&self) -> impl Iterator<Item = &PathBuf> {
+    self.expanded_paths.iter()
+  }
 }
 
 pub struct FileTreeState {
   pub root_entry: TreeEntry,
   pub expanded_paths: ExpandedPaths,
+  pub marked: BTreeSet<PathBuf>,
+  /// Styles of entries matching `style-rule` patterns
+  pub style_rules: StyleRules,
   lines: StatefulList<TreeEntryLine>,
 }
 
@@ -53,6 +64,8 @@ impl FileTreeState {
       root_entry: TreeEntry::new(path),
       lines: StatefulList::new(),
       expanded_paths: ExpandedPaths::default(),
+      marked: BTreeSet::new(),
+      style_rules: StyleRules::default(),
     };
     res.expanded_paths.expand(&res.root_entry.path);
     res.lines.state.select(Some(0));
@@ -88,7 +101,7 @@ impl FileTreeState {
   /// Rescan the file system and rebuild the list
   pub fn update(&mut self, cfg: &Config) {
     let selected = self.line().map(|x| x.path.clone());
-    self.root_entry.update(&self.expanded_paths);
+    self.root_entry.update(&self.expanded_paths, cfg.sort_by);
     self.rebuild_list(cfg);
     if let Some(x) = selected {
       self.select_path(&x);
@@ -173,7 +186,32 @@ impl FileTreeState {
   /// Rebuild the list from the file tree.
   /// Does not rescan the filesystem
   fn rebuild_list(&mut self, cfg: &Config) {
-    self.lines.items = self.root_entry.build_lines_rec(cfg, 0).collect();
+    self.marked.retain(|p| p.symlink_metadata().is_ok());
+    let marked = &self.marked;
+    self.lines.items = self
+      .root_entry
+      .build_lines_rec(cfg, &self.style_rules, 0)
+      .map(|mut line| {
+        if marked.contains(&line.path) {
+          if let Some((_, style)) = line.line.last_mut() {
+            *style = style.patch(cfg.style.mark);
+          }
+        }
+        line
+      })
+      .collect();
+  }
+
+  /// Toggle the mark on the selected entry
+  pub fn toggle_mark(&mut self) {
+    let path = self.entry().path.clone();
+    if !self.marked.remove(&path) {
+      self.marked.insert(path);
+    }
+  }
+
+  pub fn clear_marks(&mut self) {
+    self.marked.clear();
   }
 
   pub fn current_dir(&self) -> PathBuf {
@@ -195,7 +233,7 @@ pub struct FileTree<'a> {
 }
 
 impl<'a> FileTree<'a> {
-  pub fn new(cfg: &'a Config) -> FileTree {
+  pub fn new(cfg: &'a Config) -> FileTree<'a> {
     FileTree { cfg }
   }
 }
@@ -204,8 +242,14 @@ impl<'a> StatefulWidget for FileTree<'a> {
   type State = FileTreeState;
 
   fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-    let items: Vec<ListItem> = state.lines.items.iter().map(|x| x.make_line()).collect();
-    let list = List::new(items).highlight_style(self.cfg.highlight_style);
+    let indent = self.cfg.indent_width;
+    let items: Vec<ListItem> = state
+      .lines
+      .items
+      .iter()
+      .map(|x| x.make_line(indent))
+      .collect();
+    let list = List::new(items).highlight_style(self.cfg.style.highlight);
     list.render(area, buf, &mut state.lines.state);
   }
 }
@@ -217,6 +261,12 @@ pub struct TreeEntry {
   pub is_link: bool,
   pub children: Vec<TreeEntry>,
   expanded: bool,
+  /// Attributes read by the last `update`, for the style of the entry
+  info: EntryInfo,
+  /// Modification time read by the last `update`
+  mtime: Option<SystemTime>,
+  /// The detected MIME type, with the modification time it was detected at
+  mime: Cell<Option<(Option<SystemTime>, &'static str)>>,
 }
 
 /// A line in the FileTree widget.
@@ -228,10 +278,10 @@ pub struct TreeEntryLine {
 }
 
 impl TreeEntryLine {
-  fn make_line(&self) -> ListItem {
+  fn make_line(&self, indent_width: usize) -> ListItem<'_> {
     ListItem::new(Spans(
       iter::once(Span::styled(
-        "  ".repeat(self.level),
+        " ".repeat(self.level * indent_width),
         self.line.first().map(|(_, s)| *s).unwrap_or_default(),
       ))
       .chain(self.line.iter().map(|(x, s)| Span::styled(x, *s)))
@@ -248,28 +298,60 @@ impl TreeEntry {
       .absolutize()
       .map(PathBuf::from)
       .unwrap_or(path);
-    let md = path.metadata();
-    let is_link = path.as_path().read_link().is_ok();
-    TreeEntry {
+    let mut res = TreeEntry {
       path,
-      is_dir: md.map(|m| m.is_dir()).unwrap_or(false),
-      is_link,
+      is_dir: false,
+      is_link: false,
       children: vec![],
       expanded: false,
+      info: EntryInfo::from_metadata(None, false),
+      mtime: None,
+      mime: Cell::new(None),
+    };
+    res.read_metadata();
+    res
+  }
+
+  /// Read the attributes of the entry, following symlinks
+  fn read_metadata(&mut self) {
+    let link_md = self.path.symlink_metadata().ok();
+    self.is_link = link_md
+      .as_ref()
+      .map(|m| m.file_type().is_symlink())
+      .unwrap_or(false);
+    let md = match self.is_link {
+      true => self.path.metadata().ok(),
+      false => link_md,
+    };
+    self.is_dir = md.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+    self.mtime = md.as_ref().and_then(|m| m.modified().ok());
+    self.info = EntryInfo::from_metadata(md.as_ref(), self.is_link);
+  }
+
+  /// The MIME type of the entry, detected again when the file was modified
+  pub fn mime(&self) -> &'static str {
+    match self.mime.get() {
+      Some((t, mime)) if t == self.mtime => mime,
+      _ => {
+        let mime = mime::detect(&self.path);
+        self.mime.set(Some((self.mtime, mime)));
+        mime
+      }
     }
   }
 
-  fn update(&mut self, expanded: &ExpandedPaths) {
+  fn update(&mut self, expanded: &ExpandedPaths, sort_by: SortBy) {
+    self.read_metadata();
     self.expanded = expanded.is_expanded(&self.path);
     if self.expanded {
-      self.read_fs()
+      self.read_fs(sort_by)
     }
     for child in &mut self.children {
-      child.update(expanded)
+      child.update(expanded, sort_by)
     }
   }
 
-  pub fn read_fs(&mut self) {
+  pub fn read_fs(&mut self, sort_by: SortBy) {
     self.children = std::fs::read_dir(&self.path)
       .map(|paths| {
         paths
@@ -288,8 +370,24 @@ impl TreeEntry {
           .collect()
       })
       .unwrap_or_default();
-    self.children.sort_by(|a, b| a.path.cmp(&b.path));
-    self.children.sort_by(|a, b| b.is_dir.cmp(&a.is_dir));
+    self.sort_children(sort_by);
+  }
+
+  /// Sort the children, directories first
+  fn sort_children(&mut self, sort_by: SortBy) {
+    let children = &mut self.children;
+    match sort_by {
+      SortBy::Name => children.sort_by(|a, b| a.path.cmp(&b.path)),
+      SortBy::Extension => {
+        children.sort_by(|a, b| (a.path.extension(), &a.path).cmp(&(b.path.extension(), &b.path)))
+      }
+      SortBy::Size => children.sort_by_cached_key(|e| {
+        let size = e.path.metadata().map(|m| m.len()).unwrap_or(0);
+        (Reverse(size), e.path.clone())
+      }),
+      SortBy::Mtime => children.sort_by_cached_key(|e| (Reverse(e.mtime), e.path.clone())),
+    }
+    children.sort_by_key(|e| !e.is_dir);
   }
 
   fn should_show_item(&self, conf: &Config, level: usize) -> bool {
@@ -297,17 +395,7 @@ impl TreeEntry {
     if level == 0 {
       return true;
     }
-    let hidden = !conf.show_hidden
-      && self
-        .path
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(|x| x.starts_with('.'))
-        .unwrap_or(false);
-    if hidden {
-      return false;
-    }
-    true
+    !conf.is_hidden(&self.path)
   }
 
   // https://www.nerdfonts.com/cheat-sheet
@@ -329,7 +417,28 @@ impl TreeEntry {
     }
   }
 
-  pub fn build_line(&self, conf: &Config, level: usize) -> Option<TreeEntryLine> {
+  /// The style of the entry name, from its attributes, `ls_colors` and the
+  /// style rules
+  fn name_style(&self, conf: &Config, rules: &StyleRules) -> Style {
+    let mut style = self.info.style(&conf.style);
+    if conf.ls_colors {
+      if let Some(ls_style) = LS_COLORS.style(&self.info, &self.path) {
+        style = style.patch(ls_style);
+      }
+    }
+    style = rules.apply(style, &self.path, || self.mime());
+    if conf.is_hidden_file(&self.path) {
+      style = style.patch(conf.style.hidden);
+    }
+    style
+  }
+
+  pub fn build_line(
+    &self,
+    conf: &Config,
+    rules: &StyleRules,
+    level: usize,
+  ) -> Option<TreeEntryLine> {
     if !self.should_show_item(conf, level) {
       return None;
     }
@@ -347,20 +456,11 @@ impl TreeEntry {
         };
         format!("{arrow} {icon}")
       };
-      let mainstyle = if self.is_dir {
-        conf.dir_name_style
-      } else {
-        conf.file_name_style
-      };
-      let mainstyle = if self.is_link {
-        mainstyle.patch(conf.link_style)
-      } else {
-        mainstyle
-      };
+      let mainstyle = self.name_style(conf, rules);
       TreeEntryLine {
         path: self.path.clone(),
         line: vec![
-          (prefix, conf.icon_style),
+          (prefix, conf.style.icon),
           (" ".to_string() + name, mainstyle),
         ],
         level,
@@ -371,16 +471,17 @@ impl TreeEntry {
   pub fn build_lines_rec<'a>(
     &'a self,
     conf: &'a Config,
+    rules: &'a StyleRules,
     level: usize,
   ) -> Box<dyn Iterator<Item = TreeEntryLine> + 'a> {
-    let line = self.build_line(conf, level);
+    let line = self.build_line(conf, rules, level);
     if line.is_some() && self.expanded {
       Box::new(
         line.into_iter().chain(
           self
             .children
             .iter()
-            .flat_map(move |n| n.build_lines_rec(conf, level + 1)),
+            .flat_map(move |n| n.build_lines_rec(conf, rules, level + 1)),
         ),
       )
     } else {
@@ -0,0 +1,157 @@
+use crate::commands::Command;
+use crate::util::Glob;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Events hooks can be registered for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HookEvent {
+  /// An option was changed. The parameter is the option name
+  OptionChanged,
+  /// Another entry was selected. The parameter is its path
+  SelectionChanged,
+  /// The root directory changed. The parameter is the new root
+  RootChanged,
+  /// An entry was opened. The parameter is its path
+  EntryOpened,
+  /// A file or directory was created. The parameter is its path
+  FileCreated,
+  /// A file or directory was deleted. The parameter is its path
+  FileDeleted,
+  /// The config was loaded and the tree is shown
+  Startup,
+  /// sidetree is about to exit
+  Exit,
+}
+
+impl HookEvent {
+  pub const ALL: &'static [HookEvent] = &[
+    HookEvent::OptionChanged,
+    HookEvent::SelectionChanged,
+    HookEvent::RootChanged,
+    HookEvent::EntryOpened,
+    HookEvent::FileCreated,
+    HookEvent::FileDeleted,
+    HookEvent::Startup,
+    HookEvent::Exit,
+  ];
+
+  pub fn name(self) -> &'static str {
+    match self {
+      HookEvent::OptionChanged => "OptionChanged",
+      HookEvent::SelectionChanged => "SelectionChanged",
+      HookEvent::RootChanged => "RootChanged",
+      HookEvent::EntryOpened => "EntryOpened",
+      HookEvent::FileCreated => "FileCreated",
+      HookEvent::FileDeleted => "FileDeleted",
+      HookEvent::Startup => "Startup",
+      HookEvent::Exit => "Exit",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Result<HookEvent, String> {
+    HookEvent::ALL
+      .iter()
+      .copied()
+      .find(|e| e.name() == name)
+      .ok_or_else(|| format!("unknown hook event {}", name))
+  }
+
+  /// Whether hooks for this event can be filtered by their parameter
+  pub fn has_param(self) -> bool {
+    !matches!(self, HookEvent::Startup | HookEvent::Exit)
+  }
+}
+
+/// Commands to run when an event happens.
+/// If `filter` is set, the hook only runs if it matches the event parameter:
+/// option names are matched as globs, and paths as in `if -glob`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+  pub event: HookEvent,
+  pub filter: Option<Glob>,
+  pub cmds: Vec<Command>,
+}
+
+impl Hook {
+  fn matches(&self, event: HookEvent, param: &str) -> bool {
+    if self.event != event {
+      return false;
+    }
+    match (&self.filter, event) {
+      (None, _) => true,
+      (Some(filter), HookEvent::OptionChanged) => filter.matches(param),
+      (Some(filter), _) => filter.matches_path(Path::new(param)),
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct Hooks {
+  hooks: Vec<Hook>,
+  /// Events whose hooks are currently running. They are not triggered again
+  /// until the hooks finish, so hooks cannot trigger themselves endlessly
+  running: HashSet<HookEvent>,
+}
+
+impl Hooks {
+  pub fn add(&mut self, hook: Hook) {
+    self.hooks.push(hook);
+  }
+
+  /// Commands of all hooks matching the event, in the order they were added.
+  /// Empty if the hooks of this event are already running
+  pub fn matching(&self, event: HookEvent, param: &str) -> Vec<Command> {
+    if self.running.contains(&event) {
+      return vec![];
+    }
+    self
+      .hooks
+      .iter()
+      .filter(|h| h.matches(event, param))
+      .flat_map(|h| h.cmds.iter().cloned())
+      .collect()
+  }
+
+  pub fn set_running(&mut self, event: HookEvent, running: bool) {
+    if running {
+      self.running.insert(event);
+    } else {
+      self.running.remove(&event);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::hooks::*;
+
+  #[test]
+  fn hooks_match_filters() {
+    let mut hooks = Hooks::default();
+    let hook = |event, filter: Option<&str>, msg: &str| Hook {
+      event,
+      filter: filter.map(|f| Glob::new(f).unwrap()),
+      cmds: vec![Command::Echo(msg.to_string())],
+    };
+    hooks.add(hook(HookEvent::OptionChanged, Some("style.*"), "style"));
+    hooks.add(hook(HookEvent::OptionChanged, None, "any"));
+    hooks.add(hook(HookEvent::EntryOpened, Some("*.md"), "md"));
+    let echo = |s: &str| Command::Echo(s.to_string());
+    assert_eq!(
+      hooks.matching(HookEvent::OptionChanged, "style.dir"),
+      vec![echo("style"), echo("any")]
+    );
+    assert_eq!(
+      hooks.matching(HookEvent::OptionChanged, "show_hidden"),
+      vec![echo("any")]
+    );
+    assert_eq!(
+      hooks.matching(HookEvent::EntryOpened, "/a/b.md"),
+      vec![echo("md")]
+    );
+    assert!(hooks.matching(HookEvent::EntryOpened, "/a/b.rs").is_empty());
+    hooks.set_running(HookEvent::OptionChanged, true);
+    assert!(hooks.matching(HookEvent::OptionChanged, "x").is_empty());
+  }
+}
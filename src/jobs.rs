@@ -0,0 +1,95 @@
+use crate::util::event::Event;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+use termion::event::{Key, MouseEvent};
+
+/// A shell command running in the background
+pub struct Job {
+  pub id: usize,
+  pub cmd: String,
+  pub pid: u32,
+}
+
+/// Sent through the event channel when a background job exits
+pub struct JobDone {
+  pub id: usize,
+  pub status: std::io::Result<ExitStatus>,
+  pub stderr: String,
+}
+
+pub struct Jobs {
+  next_id: usize,
+  running: Vec<Job>,
+  tx: Option<Sender<Event<Key, MouseEvent>>>,
+}
+
+impl Jobs {
+  pub fn new() -> Jobs {
+    Jobs {
+      next_id: 1,
+      running: vec![],
+      tx: None,
+    }
+  }
+
+  /// Set the channel job completion events are sent to
+  pub fn set_sender(&mut self, tx: Sender<Event<Key, MouseEvent>>) {
+    self.tx = Some(tx);
+  }
+
+  /// Start `command` in a background thread. Returns the job id
+  pub fn spawn(&mut self, mut command: Command, cmd: &str) -> Result<usize, String> {
+    let tx = self.tx.clone().ok_or("background jobs are not available")?;
+    // Run in its own process group, so killing the job kills its children too
+    let child = command
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::piped())
+      .process_group(0)
+      .spawn()
+      .map_err(|e| e.to_string())?;
+    let id = self.next_id;
+    self.next_id += 1;
+    self.running.push(Job {
+      id,
+      cmd: cmd.to_string(),
+      pid: child.id(),
+    });
+    thread::spawn(move || {
+      let (status, stderr) = match child.wait_with_output() {
+        Ok(output) => (
+          Ok(output.status),
+          String::from_utf8_lossy(&output.stderr).into_owned(),
+        ),
+        Err(err) => (Err(err), String::new()),
+      };
+      let _ = tx.send(Event::JobDone(JobDone { id, status, stderr }));
+    });
+    Ok(id)
+  }
+
+  /// Remove a job that has exited from the list of running jobs
+  pub fn finish(&mut self, id: usize) -> Option<Job> {
+    let idx = self.running.iter().position(|j| j.id == id)?;
+    Some(self.running.remove(idx))
+  }
+
+  /// Send SIGTERM to the process group of a running job
+  pub fn kill(&mut self, id: usize) -> Result<(), String> {
+    let job = self
+      .running
+      .iter()
+      .find(|j| j.id == id)
+      .ok_or_else(|| format!("no running job {}", id))?;
+    if unsafe { libc::kill(-(job.pid as libc::pid_t), libc::SIGTERM) } != 0 {
+      return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(())
+  }
+
+  pub fn running(&self) -> &[Job] {
+    &self.running
+  }
+}
@@ -1,19 +1,29 @@
 mod app;
 mod cache;
+mod colors;
 mod commands;
 mod config;
+mod config_layers;
+mod file_ops;
 mod file_tree;
+mod hooks;
 mod icons;
+mod jobs;
 mod keymap;
+mod mime;
+mod open_rules;
+mod popup;
 mod prompt;
+mod style_rules;
 mod util;
 
 use crate::commands::Command;
 use crate::{app::App, cache::Cache};
-use std::{fs::File, path::PathBuf};
+use std::path::PathBuf;
 
 use clap::Parser;
 use commands::parse_cmds;
+use hooks::HookEvent;
 use std::error::Error;
 use std::io;
 use termion::input::MouseTerminal;
@@ -37,7 +47,9 @@ struct Opts {
   #[clap(default_value = ".")]
   directory: PathBuf,
 
-  /// Set a config file to use. By default uses $XDG_CONFIG_DIR/sidetree/sidetreerc
+  /// Set a config file to use. By default uses $XDG_CONFIG_DIR/sidetree/sidetreerc.
+  /// It is loaded after the built-in defaults and system configs, and before
+  /// a trusted project-local .sidetreerc
   #[clap(short, long)]
   config: Option<PathBuf>,
 
@@ -55,24 +67,13 @@ struct Opts {
   exec: Option<String>,
 }
 
-const DEFAULT_CONFIG: &str = include_str!("../sidetreerc");
-
-fn default_conf_file() -> PathBuf {
-  let xdg = xdg::BaseDirectories::with_prefix("sidetree").unwrap();
-  let conf_file = xdg
-    .place_config_file("sidetreerc")
-    .expect("Cannot create config directory");
-  if !conf_file.exists() {
-    File::create(&conf_file).expect("Cannot create config file");
-    std::fs::write(&conf_file, DEFAULT_CONFIG).expect("Couldn't write default config file");
-  }
-  conf_file
-}
-
 fn main() -> Result<(), Box<dyn Error>> {
   let opts = Opts::parse();
 
   // Terminal initialization
+  util::term::save_termios();
+  util::term::install_panic_hook();
+  util::signal::install_reload_handler();
   let stdout = io::stdout().into_raw_mode()?;
   let stdout = MouseTerminal::from(stdout);
   let stdout = AlternateScreen::from(stdout);
@@ -81,18 +82,28 @@ fn main() -> Result<(), Box<dyn Error>> {
 
   let events = Events::new();
 
+  let mut cache_warning = None;
   let cache = if !opts.no_cache {
-    Cache::from_file(&Cache::default_file_path()).expect("Failed to read cache file")
+    Cache::from_file(&Cache::default_file_path()).unwrap_or_else(|err| {
+      cache_warning = Some(err);
+      Cache::default()
+    })
   } else {
     Cache::default()
   };
 
-  let mut app = App::new(cache);
-  let conf_file = opts.config.unwrap_or_else(default_conf_file);
+  let mut app = App::new(cache.clone());
+  app.jobs.set_sender(events.sender());
+  if let Some(warning) = cache_warning {
+    app.statusline.info.warning(&warning);
+  }
+  let conf_file = opts
+    .config
+    .unwrap_or_else(config_layers::default_user_config);
 
-  app.run_script_file(&conf_file)?;
-  if opts.exec.is_some() {
-    app.run_commands(&parse_cmds(&opts.exec.unwrap())?)
+  app.load_config(&conf_file, &opts.directory);
+  if let Some(exec) = opts.exec {
+    app.run_commands(&parse_cmds(&exec)?)
   }
 
   app.tree.change_root(&app.config, opts.directory);
@@ -102,8 +113,13 @@ fn main() -> Result<(), Box<dyn Error>> {
     app.tree.update(&app.config);
     app.tree.select_path(&path);
   }
+  app.startup();
 
   loop {
+    if app.needs_clear {
+      terminal.clear()?;
+      app.needs_clear = false;
+    }
     terminal.draw(|f| {
       app.draw(f);
     })?;
@@ -115,6 +131,9 @@ fn main() -> Result<(), Box<dyn Error>> {
       Event::Mouse(mouse) => {
         app.on_mouse(mouse);
       }
+      Event::JobDone(done) => {
+        app.on_job_done(done);
+      }
       _ => (),
     }
 
@@ -123,9 +142,16 @@ fn main() -> Result<(), Box<dyn Error>> {
       break;
     }
   }
+  app.fire_hook(HookEvent::Exit, "");
 
   if !opts.no_cache {
-    app.get_cache().write_file(&Cache::default_file_path())
+    let res = app
+      .get_cache()
+      .write_file(&cache, &Cache::default_file_path());
+    drop(terminal);
+    if let Err(err) = res {
+      eprintln!("{}", err);
+    }
   }
 
   Ok(())
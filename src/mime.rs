@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+/// Number of bytes read from the start of a file to detect its type
+const SNIFF_LEN: usize = 512;
+
+/// Bytes expected at an offset of a file
+type MagicPart = (usize, &'static [u8]);
+
+/// File signatures: every part has to match.
+/// More specific signatures come first
+const MAGIC: &[(&[MagicPart], &str)] = &[
+  (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png"),
+  (&[(0, b"\xff\xd8\xff")], "image/jpeg"),
+  (&[(0, b"GIF87a")], "image/gif"),
+  (&[(0, b"GIF89a")], "image/gif"),
+  (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp"),
+  (&[(0, b"RIFF"), (8, b"WAVE")], "audio/wav"),
+  (&[(0, b"RIFF"), (8, b"AVI ")], "video/x-msvideo"),
+  (&[(0, b"II*\0")], "image/tiff"),
+  (&[(0, b"MM\0*")], "image/tiff"),
+  (&[(0, b"\0\0\x01\0")], "image/vnd.microsoft.icon"),
+  (&[(0, b"BM")], "image/bmp"),
+  (&[(4, b"ftypavif")], "image/avif"),
+  (&[(4, b"ftypheic")], "image/heic"),
+  (&[(4, b"ftypqt")], "video/quicktime"),
+  (&[(4, b"ftypM4A")], "audio/mp4"),
+  (&[(4, b"ftyp")], "video/mp4"),
+  (&[(0, b"\x1a\x45\xdf\xa3")], "video/x-matroska"),
+  (&[(0, b"OggS")], "audio/ogg"),
+  (&[(0, b"fLaC")], "audio/flac"),
+  (&[(0, b"ID3")], "audio/mpeg"),
+  (&[(0, b"%PDF-")], "application/pdf"),
+  (&[(0, b"%!PS")], "application/postscript"),
+  (&[(0, b"{\\rtf")], "text/rtf"),
+  (&[(0, b"PK\x03\x04")], "application/zip"),
+  (&[(0, b"\x1f\x8b")], "application/gzip"),
+  (&[(0, b"BZh")], "application/x-bzip2"),
+  (&[(0, b"\xfd7zXZ\0")], "application/x-xz"),
+  (&[(0, b"\x28\xb5\x2f\xfd")], "application/zstd"),
+  (&[(0, b"7z\xbc\xaf\x27\x1c")], "application/x-7z-compressed"),
+  (&[(0, b"Rar!\x1a\x07")], "application/vnd.rar"),
+  (&[(257, b"ustar")], "application/x-tar"),
+  (&[(0, b"\x7fELF")], "application/x-executable"),
+  (&[(0, b"\0asm")], "application/wasm"),
+  (&[(0, b"SQLite format 3\0")], "application/vnd.sqlite3"),
+  (&[(0, b"wOFF")], "font/woff"),
+  (&[(0, b"wOF2")], "font/woff2"),
+  (&[(0, b"OTTO")], "font/otf"),
+];
+
+/// Signatures shorter than this are found at the start of other files too,
+/// i.e. a text file starting with `BM`, so the extension is preferred for them
+const STRONG_MAGIC_LEN: usize = 4;
+
+/// Formats that other formats are built on, i.e. `.docx` and `.epub` files
+/// are zip archives. The extension is preferred for these
+const CONTAINERS: &[&str] = &[
+  "application/zip",
+  "application/gzip",
+  "audio/ogg",
+  "video/mp4",
+  "video/x-matroska",
+];
+
+/// Types by file extension, matched case insensitively
+const EXTENSIONS: &[(&str, &str)] = &[
+  ("7z", "application/x-7z-compressed"),
+  ("avi", "video/x-msvideo"),
+  ("avif", "image/avif"),
+  ("bash", "text/x-shellscript"),
+  ("bmp", "image/bmp"),
+  ("bz2", "application/x-bzip2"),
+  ("c", "text/x-c"),
+  ("cc", "text/x-c++"),
+  ("cpp", "text/x-c++"),
+  ("css", "text/css"),
+  ("csv", "text/csv"),
+  ("cxx", "text/x-c++"),
+  ("db", "application/vnd.sqlite3"),
+  ("diff", "text/x-diff"),
+  (
+    "docx",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+  ),
+  ("epub", "application/epub+zip"),
+  ("flac", "audio/flac"),
+  ("gif", "image/gif"),
+  ("go", "text/x-go"),
+  ("gz", "application/gzip"),
+  ("h", "text/x-c"),
+  ("heic", "image/heic"),
+  ("hpp", "text/x-c++"),
+  ("hs", "text/x-haskell"),
+  ("htm", "text/html"),
+  ("html", "text/html"),
+  ("ico", "image/vnd.microsoft.icon"),
+  ("ini", "text/plain"),
+  ("iso", "application/x-iso9660-image"),
+  ("jar", "application/java-archive"),
+  ("java", "text/x-java"),
+  ("jpeg", "image/jpeg"),
+  ("jpg", "image/jpeg"),
+  ("js", "text/javascript"),
+  ("json", "application/json"),
+  ("log", "text/plain"),
+  ("lua", "text/x-lua"),
+  ("m4a", "audio/mp4"),
+  ("md", "text/markdown"),
+  ("mjs", "text/javascript"),
+  ("mkv", "video/x-matroska"),
+  ("mov", "video/quicktime"),
+  ("mp3", "audio/mpeg"),
+  ("mp4", "video/mp4"),
+  ("odp", "application/vnd.oasis.opendocument.presentation"),
+  ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+  ("odt", "application/vnd.oasis.opendocument.text"),
+  ("oga", "audio/ogg"),
+  ("ogg", "audio/ogg"),
+  ("ogv", "video/ogg"),
+  ("opus", "audio/opus"),
+  ("otf", "font/otf"),
+  ("patch", "text/x-diff"),
+  ("pdf", "application/pdf"),
+  ("png", "image/png"),
+  (
+    "pptx",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+  ),
+  ("ps", "application/postscript"),
+  ("py", "text/x-python"),
+  ("rar", "application/vnd.rar"),
+  ("rb", "text/x-ruby"),
+  ("rs", "text/x-rust"),
+  ("sh", "text/x-shellscript"),
+  ("sqlite", "application/vnd.sqlite3"),
+  ("svg", "image/svg+xml"),
+  ("tar", "application/x-tar"),
+  ("tex", "text/x-tex"),
+  ("tgz", "application/gzip"),
+  ("tif", "image/tiff"),
+  ("tiff", "image/tiff"),
+  ("toml", "application/toml"),
+  ("ts", "text/x-typescript"),
+  ("ttf", "font/ttf"),
+  ("txt", "text/plain"),
+  ("wasm", "application/wasm"),
+  ("wav", "audio/wav"),
+  ("webm", "video/webm"),
+  ("webp", "image/webp"),
+  ("woff", "font/woff"),
+  ("woff2", "font/woff2"),
+  (
+    "xlsx",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+  ),
+  ("xml", "text/xml"),
+  ("xz", "application/x-xz"),
+  ("yaml", "application/yaml"),
+  ("yml", "application/yaml"),
+  ("zip", "application/zip"),
+  ("zsh", "text/x-shellscript"),
+  ("zst", "application/zstd"),
+];
+
+/// The type of the first matching signature, and whether the signature is
+/// long enough to be trusted over the extension
+fn by_magic(bytes: &[u8]) -> Option<(&'static str, bool)> {
+  MAGIC
+    .iter()
+    .find(|(parts, _)| {
+      parts
+        .iter()
+        .all(|(offset, magic)| bytes.get(*offset..offset + magic.len()) == Some(*magic))
+    })
+    .map(|(parts, mime)| {
+      let len: usize = parts.iter().map(|(_, magic)| magic.len()).sum();
+      (*mime, len >= STRONG_MAGIC_LEN && !CONTAINERS.contains(mime))
+    })
+}
+
+fn by_extension(path: &Path) -> Option<&'static str> {
+  let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+  EXTENSIONS
+    .iter()
+    .find(|(e, _)| *e == ext)
+    .map(|(_, mime)| *mime)
+}
+
+/// Type of files without a signature or known extension, by their contents
+fn by_contents(bytes: &[u8]) -> &'static str {
+  let text = !bytes.contains(&0)
+    && match std::str::from_utf8(bytes) {
+      Ok(_) => true,
+      // The sample may end in the middle of a character
+      Err(e) => e.error_len().is_none(),
+    };
+  if !text {
+    "application/octet-stream"
+  } else if bytes.starts_with(b"#!") {
+    "text/x-shellscript"
+  } else if bytes.starts_with(b"<?xml") {
+    "text/xml"
+  } else {
+    "text/plain"
+  }
+}
+
+/// The MIME type of a file, from its signature and extension.
+/// Entries that are not regular files get `inode/*` types like `file(1)`.
+pub fn detect(path: &Path) -> &'static str {
+  let md = match path.metadata() {
+    Ok(md) => md,
+    Err(_) if path.symlink_metadata().is_ok() => return "inode/symlink",
+    Err(_) => return "application/octet-stream",
+  };
+  let ft = md.file_type();
+  if ft.is_dir() {
+    return "inode/directory";
+  } else if ft.is_fifo() {
+    return "inode/fifo";
+  } else if ft.is_socket() {
+    return "inode/socket";
+  } else if ft.is_block_device() {
+    return "inode/blockdevice";
+  } else if ft.is_char_device() {
+    return "inode/chardevice";
+  }
+
+  let ext = by_extension(path);
+  let mut bytes = Vec::with_capacity(SNIFF_LEN);
+  let read = File::open(path).and_then(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut bytes));
+  if read.is_err() {
+    return ext.unwrap_or("application/octet-stream");
+  }
+  if bytes.is_empty() {
+    return ext.unwrap_or("inode/x-empty");
+  }
+  match by_magic(&bytes) {
+    Some((mime, true)) => mime,
+    Some((mime, false)) => ext.unwrap_or(mime),
+    None => ext.unwrap_or_else(|| by_contents(&bytes)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::mime::*;
+  use crate::util::TempDir;
+
+  #[test]
+  fn detect_types() {
+    let dir = TempDir::new("sidetree-mime-");
+    let file = |name: &str, contents: &[u8]| {
+      let path = dir.join(name);
+      std::fs::write(&path, contents).unwrap();
+      detect(&path)
+    };
+    assert_eq!(detect(&dir), "inode/directory");
+    assert_eq!(file("a.txt", b"\x89PNG\r\n\x1a\n...."), "image/png");
+    assert_eq!(file("b", b"%PDF-1.4"), "application/pdf");
+    assert_eq!(
+      file("c.docx", b"PK\x03\x04...."),
+      "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+    );
+    assert_eq!(file("d", b"PK\x03\x04...."), "application/zip");
+    assert_eq!(file("e.rs", b"fn main() {}"), "text/x-rust");
+    assert_eq!(file("f", b"#!/bin/sh\necho"), "text/x-shellscript");
+    assert_eq!(file("g", "caf\u{e9}".as_bytes()), "text/plain");
+    assert_eq!(file("h", b"\x01\x02\0\x03"), "application/octet-stream");
+    assert_eq!(file("i.md", b""), "text/markdown");
+    // Short signatures do not override known extensions
+    assert_eq!(file("notes.md", b"BMW drivers"), "text/markdown");
+    assert_eq!(file("tags.txt", b"ID3 tags"), "text/plain");
+    assert_eq!(file("k", b"BM\x36\x00\x00"), "image/bmp");
+    assert_eq!(file("l.txt", b"%PDF-1.4"), "application/pdf");
+    assert_eq!(file("j", b""), "inode/x-empty");
+    std::os::unix::fs::symlink(dir.join("missing"), dir.join("link")).unwrap();
+    assert_eq!(detect(&dir.join("link")), "inode/symlink");
+  }
+}
@@ -0,0 +1,138 @@
+use crate::util::Glob;
+use std::path::Path;
+
+/// Pattern selecting the entries a rule applies to
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryPattern {
+  /// A glob matched as in `if -glob`
+  Glob(Glob),
+  /// A glob matched against the MIME type, i.e. `image/*`
+  Mime(Glob),
+}
+
+impl EntryPattern {
+  /// Compile `pattern`, matched against the MIME type if `mime` is set
+  pub fn new(pattern: &str, mime: bool) -> Result<EntryPattern, String> {
+    let glob = Glob::new(pattern)?;
+    Ok(match mime {
+      true => EntryPattern::Mime(glob),
+      false => EntryPattern::Glob(glob),
+    })
+  }
+
+  pub fn matches(&self, path: &Path, mime: &str) -> bool {
+    match self {
+      EntryPattern::Glob(pattern) => pattern.matches_path(path),
+      EntryPattern::Mime(pattern) => pattern.matches(mime),
+    }
+  }
+}
+
+/// A shell command to open the entries matching a pattern with
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenRule {
+  /// Name to pick the rule with `open -with`. Several rules can share a name
+  /// to open different patterns with the same program
+  pub name: String,
+  pub pattern: EntryPattern,
+  pub cmd: String,
+}
+
+impl OpenRule {
+  /// A rule named after the first word of its command
+  pub fn new(name: Option<String>, pattern: EntryPattern, cmd: String) -> OpenRule {
+    let name = name.unwrap_or_else(|| cmd.split_whitespace().next().unwrap_or("").to_string());
+    OpenRule { name, pattern, cmd }
+  }
+
+  pub fn matches(&self, path: &Path, mime: &str) -> bool {
+    self.pattern.matches(path, mime)
+  }
+}
+
+#[derive(Default)]
+pub struct OpenRules {
+  rules: Vec<OpenRule>,
+}
+
+impl OpenRules {
+  /// Add a rule. It replaces a rule with the same name and pattern, and
+  /// otherwise takes precedence over the rules added before
+  pub fn add(&mut self, rule: OpenRule) {
+    self
+      .rules
+      .retain(|r| r.name != rule.name || r.pattern != rule.pattern);
+    self.rules.push(rule);
+  }
+
+  /// The rule to open `path` with, i.e. the last added rule matching it
+  pub fn find(&self, path: &Path, mime: &str) -> Option<&OpenRule> {
+    self.rules.iter().rev().find(|r| r.matches(path, mime))
+  }
+
+  /// A rule by name, preferring rules matching `path`
+  pub fn get(&self, name: &str, path: &Path, mime: &str) -> Option<&OpenRule> {
+    let mut named = self.rules.iter().rev().filter(|r| r.name == name);
+    named
+      .clone()
+      .find(|r| r.matches(path, mime))
+      .or_else(|| named.next())
+  }
+
+  /// One rule per name, rules matching `path` first, in order of precedence
+  pub fn candidates(&self, path: &Path, mime: &str) -> Vec<&OpenRule> {
+    let (mut res, rest): (Vec<&OpenRule>, Vec<&OpenRule>) =
+      self.rules.iter().rev().partition(|r| r.matches(path, mime));
+    res.extend(rest);
+    let mut names = vec![];
+    res.retain(|r| {
+      let new = !names.contains(&&r.name);
+      names.push(&r.name);
+      new
+    });
+    res
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::open_rules::*;
+
+  #[test]
+  fn rules_by_pattern_and_name() {
+    let mut rules = OpenRules::default();
+    let glob = |p: &str| EntryPattern::new(p, false).unwrap();
+    let rule = |name: Option<&str>, pattern: EntryPattern, cmd: &str| {
+      OpenRule::new(name.map(String::from), pattern, cmd.to_string())
+    };
+    rules.add(rule(None, glob("*.pdf"), "zathura \"$sidetree_entry\""));
+    rules.add(rule(None, glob("*.png"), "feh \"$sidetree_entry\""));
+    rules.add(rule(
+      Some("view"),
+      glob("*.png"),
+      "sxiv \"$sidetree_entry\"",
+    ));
+    rules.add(rule(None, glob("*.jpg"), "feh \"$sidetree_entry\""));
+    let png = Path::new("/a/b.png");
+    let rs = Path::new("c.rs");
+    assert_eq!(rules.find(png, "image/png").unwrap().name, "view");
+    assert_eq!(rules.find(Path::new("c.pdf"), "").unwrap().name, "zathura");
+    assert!(rules.find(rs, "text/x-rust").is_none());
+    assert_eq!(rules.get("feh", png, "").unwrap().pattern, glob("*.png"));
+    assert_eq!(rules.get("feh", rs, "").unwrap().pattern, glob("*.jpg"));
+    let names: Vec<_> = rules
+      .candidates(png, "")
+      .iter()
+      .map(|r| r.name.as_str())
+      .collect();
+    assert_eq!(names, vec!["view", "feh", "zathura"]);
+    rules.add(rule(
+      None,
+      glob("*.pdf"),
+      "zathura --fork \"$sidetree_entry\"",
+    ));
+    assert_eq!(rules.candidates(png, "").len(), 3);
+    rules.add(rule(None, EntryPattern::new("text/*", true).unwrap(), "vi"));
+    assert_eq!(rules.find(rs, "text/x-rust").unwrap().name, "vi");
+  }
+}
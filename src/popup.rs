@@ -0,0 +1,106 @@
+use crate::commands::Command;
+use crate::util::StatefulList;
+use termion::event::Key;
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::style::Style;
+use tui::widgets::{Block, Borders, Clear, List, ListItem};
+use tui::Frame;
+
+/// A line in a popup.
+/// If `action` is set, it is run when the line is submitted with `<ret>`
+pub struct PopupLine {
+  pub text: String,
+  pub style: Style,
+  pub action: Option<Command>,
+}
+
+impl PopupLine {
+  pub fn new(text: impl Into<String>, style: Style) -> PopupLine {
+    PopupLine {
+      text: text.into(),
+      style,
+      action: None,
+    }
+  }
+
+  pub fn with_action(mut self, action: Command) -> PopupLine {
+    self.action = Some(action);
+    self
+  }
+}
+
+/// A scrollable list of lines drawn on top of the tree
+pub struct Popup {
+  title: String,
+  lines: StatefulList<PopupLine>,
+  page_size: usize,
+}
+
+impl Popup {
+  pub fn new(title: impl Into<String>, lines: Vec<PopupLine>) -> Popup {
+    let mut lines = StatefulList::with_items(lines);
+    if !lines.items.is_empty() {
+      lines.select_index(0);
+    }
+    Popup {
+      title: title.into(),
+      lines,
+      page_size: 1,
+    }
+  }
+
+  /// Select the last line, i.e. for logs where the newest entry is at the bottom
+  pub fn select_last(mut self) -> Popup {
+    if !self.lines.items.is_empty() {
+      self.lines.select_index(self.lines.items.len() - 1);
+    }
+    self
+  }
+
+  fn scroll(&mut self, n: isize) {
+    if self.lines.items.is_empty() {
+      return;
+    }
+    let idx = self.lines.index().unwrap_or(0).saturating_add_signed(n);
+    self.lines.nth(idx);
+  }
+
+  /// Handle a key.
+  /// Returns true if the popup should be closed, and optionally a command to run
+  pub fn on_key(&mut self, key: Key) -> (bool, Option<Command>) {
+    match key {
+      Key::Esc | Key::Char('q') => return (true, None),
+      Key::Char('\n') => {
+        let action = self.lines.selected().and_then(|l| l.action.clone());
+        return (action.is_some(), action);
+      }
+      Key::Char('j') | Key::Down => self.scroll(1),
+      Key::Char('k') | Key::Up => self.scroll(-1),
+      Key::PageDown | Key::Ctrl('d') => self.scroll(self.page_size as isize),
+      Key::PageUp | Key::Ctrl('u') => self.scroll(-(self.page_size as isize)),
+      Key::Char('g') | Key::Home => self.scroll(isize::MIN),
+      Key::Char('G') | Key::End => self.scroll(isize::MAX),
+      _ => {}
+    }
+    (false, None)
+  }
+
+  pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, highlight_style: Style) {
+    let block = Block::default()
+      .borders(Borders::ALL)
+      .title(self.title.as_str());
+    self.page_size = block.inner(area).height.max(1) as usize;
+    let items: Vec<ListItem> = self
+      .lines
+      .items
+      .iter()
+      .map(|l| ListItem::new(l.text.as_str()).style(l.style))
+      .collect();
+    let list = List::new(items)
+      .block(block)
+      .highlight_style(highlight_style);
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut self.lines.state);
+  }
+}
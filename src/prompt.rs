@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use crate::commands::Command;
+use crate::config::Config;
+use std::time::{Duration, Instant};
 use termion::event::Key;
 use tui::backend::Backend;
 use tui::layout::Rect;
@@ -17,7 +19,9 @@ pub trait Prompt {
   fn on_cancel(&mut self) -> Option<Command> {
     None
   }
-  fn on_complete(&mut self, input: &str) -> Vec<String> {
+  /// Completions of `input`, each replacing the whole input.
+  /// `<tab>` and `<s-tab>` cycle through them
+  fn on_complete(&mut self, _input: &str) -> Vec<String> {
     Vec::new()
   }
   fn init_text(&self) -> String {
@@ -30,6 +34,8 @@ struct PromptState<'a> {
   textarea: TextArea<'a>,
   history: Vec<String>,
   hist_index: usize,
+  /// Candidates and the selected index while cycling through completions
+  completions: Option<(Vec<String>, usize)>,
 }
 
 impl<'a> PromptState<'a> {
@@ -42,10 +48,14 @@ impl<'a> PromptState<'a> {
       prompt,
       history,
       hist_index: 0,
+      completions: None,
     }
   }
   /// Returns true if the prompt should be exited
   pub fn on_key(&mut self, key: Key) -> (bool, Option<Command>) {
+    if !matches!(key, Key::Char('\t') | Key::BackTab) {
+      self.completions = None;
+    }
     match key {
       Key::Char('\n') => (true, self.submit()),
       Key::Up => {
@@ -57,6 +67,14 @@ impl<'a> PromptState<'a> {
         (false, None)
       }
       Key::Esc => (true, self.cancel()),
+      Key::Char('\t') => {
+        self.complete(1);
+        (false, None)
+      }
+      Key::BackTab => {
+        self.complete(-1);
+        (false, None)
+      }
       input => {
         self.textarea.input(input);
         self.history[0] = self.textarea.lines()[0].clone();
@@ -65,6 +83,32 @@ impl<'a> PromptState<'a> {
     }
   }
 
+  fn complete(&mut self, step: isize) {
+    let (candidates, index) = match self.completions.take() {
+      Some((candidates, index)) => {
+        let n = candidates.len() as isize;
+        let index = (index as isize + step).rem_euclid(n) as usize;
+        (candidates, index)
+      }
+      None => {
+        let candidates = self.prompt.on_complete(&self.textarea.lines()[0]);
+        if candidates.is_empty() {
+          return;
+        }
+        let index = if step < 0 { candidates.len() - 1 } else { 0 };
+        (candidates, index)
+      }
+    };
+    self.set_text(candidates[index].clone());
+    self.completions = Some((candidates, index));
+  }
+
+  fn set_text(&mut self, text: String) {
+    self.textarea = TextArea::new(vec![text]);
+    self.textarea.move_cursor(CursorMove::End);
+    self.history[0] = self.textarea.lines()[0].clone();
+  }
+
   fn walk_history(&mut self, i: isize) {
     self.hist_index = self.hist_index.saturating_add_signed(i);
     self.hist_index = self.hist_index.clamp(0, self.history.len() - 1);
@@ -78,7 +122,7 @@ impl<'a> PromptState<'a> {
     self.textarea = TextArea::default();
     cmd
   }
-  
+
   pub fn cancel(&mut self) -> Option<Command> {
     let cmd = self.prompt.on_cancel();
     self.history.remove(0);
@@ -105,24 +149,85 @@ impl<'a> PromptState<'a> {
   }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MsgLevel {
+  Info,
+  Warning,
+  Error,
+}
+
+pub struct Message {
+  pub level: MsgLevel,
+  pub text: String,
+  pub time: Instant,
+}
+
+/// Message area of the statusline.
+/// The last `log_size` messages are also kept in a log for the `messages`
+/// command.
 pub struct InfoBox {
-  info_msg: String,
+  current: Option<usize>,
+  log: VecDeque<Message>,
+  log_size: usize,
 }
 
 impl InfoBox {
   pub fn new() -> InfoBox {
     InfoBox {
-      info_msg: String::new(),
+      current: None,
+      log: VecDeque::new(),
+      log_size: usize::MAX,
+    }
+  }
+  /// Limit the number of messages in the log, dropping the oldest ones
+  pub fn set_log_size(&mut self, size: usize) {
+    self.log_size = size.max(1);
+    while self.log.len() > self.log_size {
+      self.log.pop_front();
+      self.current = self.current.and_then(|i| i.checked_sub(1));
     }
   }
+  /// Show a message, and add it to the log
+  pub fn show(&mut self, level: MsgLevel, msg: &str) {
+    self.log(level, msg);
+    self.current = Some(self.log.len() - 1);
+  }
+  /// Add a message to the log without showing it
+  pub fn log(&mut self, level: MsgLevel, msg: &str) {
+    self.log.push_back(Message {
+      level,
+      text: msg.to_string(),
+      time: Instant::now(),
+    });
+    self.set_log_size(self.log_size);
+  }
   pub fn info(&mut self, msg: &str) {
-    self.info_msg = String::from(msg);
+    self.show(MsgLevel::Info, msg)
+  }
+  pub fn warning(&mut self, msg: &str) {
+    self.show(MsgLevel::Warning, msg)
   }
   pub fn error(&mut self, msg: &str) {
-    self.info_msg = String::from(msg);
+    self.show(MsgLevel::Error, msg)
   }
   pub fn clear(&mut self) {
-    self.info_msg.clear();
+    self.current = None;
+  }
+  /// The currently shown message
+  pub fn message(&self) -> Option<&Message> {
+    self.current.and_then(|i| self.log.get(i))
+  }
+  /// The logged messages, oldest first
+  pub fn messages(&self) -> impl Iterator<Item = &Message> {
+    self.log.iter()
+  }
+  /// Clear the current message if it has been shown for longer than `timeout`
+  pub fn tick(&mut self, timeout: Option<Duration>) {
+    if let (Some(msg), Some(timeout)) = (self.message(), timeout) {
+      if msg.time.elapsed() >= timeout {
+        self.clear();
+      }
+    }
   }
 }
 
@@ -170,22 +275,38 @@ impl<'a> StatusLine<'a> {
     self.prompt_state = Some(PromptState::new(prompt, hist));
   }
 
-  pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, rect: Rect) {
+  pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, rect: Rect, cfg: &Config) {
     if let Some(prompt) = &mut self.prompt_state {
       prompt.draw(f, rect);
-    } else {
-      let text = vec![Spans::from(vec![Span::raw(self.info.info_msg.as_str())])];
+    } else if let Some(msg) = self.info.message() {
+      let text = vec![Spans::from(vec![Span::styled(
+        msg.text.as_str(),
+        cfg.msg_style(msg.level),
+      )])];
       let input = Paragraph::new(text);
       f.render_widget(input, rect);
     }
   }
-  
-  pub fn cancel_prompt(&mut self) -> Option<Command> {
-    if let Some(p) = &mut self.prompt_state {
-      let res = p.cancel();
-      self.prompt_state = None;
-      return res
-    }
-    None
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::prompt::*;
+
+  #[test]
+  fn message_log_is_capped() {
+    let mut info = InfoBox::new();
+    info.info("a");
+    info.log(MsgLevel::Error, "b");
+    info.set_log_size(2);
+    info.warning("c");
+    let texts: Vec<_> = info.messages().map(|m| m.text.as_str()).collect();
+    assert_eq!(texts, ["b", "c"]);
+    assert_eq!(info.message().map(|m| m.text.as_str()), Some("c"));
+    info.set_log_size(1);
+    assert_eq!(info.message().map(|m| m.text.as_str()), Some("c"));
+    info.info("d");
+    info.log(MsgLevel::Info, "e");
+    assert!(info.message().is_none());
   }
 }
@@ -0,0 +1,396 @@
+use crate::config::{Config, StyleConfig};
+use crate::open_rules::EntryPattern;
+use lazy_static::lazy_static;
+use std::fs::Metadata;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tui::style::{Color, Modifier, Style};
+
+/// Type of an entry, or of the target of a symlink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+  Dir,
+  File,
+  Fifo,
+  Socket,
+  BlockDevice,
+  CharDevice,
+}
+
+/// The attributes of an entry that have their own styles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryInfo {
+  pub kind: FileKind,
+  pub link: bool,
+  /// A symlink whose target does not exist
+  pub broken: bool,
+  /// A regular file with an execute bit set
+  pub exec: bool,
+  /// A regular file with the setuid bit set
+  pub setuid: bool,
+  /// A regular file with the setgid bit set
+  pub setgid: bool,
+}
+
+impl EntryInfo {
+  /// The attributes of an entry from the metadata of its target, `None` if
+  /// the target does not exist
+  pub fn from_metadata(md: Option<&Metadata>, link: bool) -> EntryInfo {
+    let md = match md {
+      Some(md) => md,
+      None => {
+        return EntryInfo {
+          kind: FileKind::File,
+          link,
+          broken: link,
+          exec: false,
+          setuid: false,
+          setgid: false,
+        }
+      }
+    };
+    let ft = md.file_type();
+    let kind = if ft.is_dir() {
+      FileKind::Dir
+    } else if ft.is_fifo() {
+      FileKind::Fifo
+    } else if ft.is_socket() {
+      FileKind::Socket
+    } else if ft.is_block_device() {
+      FileKind::BlockDevice
+    } else if ft.is_char_device() {
+      FileKind::CharDevice
+    } else {
+      FileKind::File
+    };
+    let mode = md.permissions().mode();
+    let file = kind == FileKind::File;
+    EntryInfo {
+      kind,
+      link,
+      broken: false,
+      exec: file && mode & 0o111 != 0,
+      setuid: file && mode & 0o4000 != 0,
+      setgid: file && mode & 0o2000 != 0,
+    }
+  }
+
+  /// The style of the entry from the `style.*` options
+  pub fn style(&self, styles: &StyleConfig) -> Style {
+    let mut res = match self.kind {
+      FileKind::Dir => styles.dir,
+      FileKind::File => styles.file,
+      FileKind::Fifo => styles.file.patch(styles.fifo),
+      FileKind::Socket => styles.file.patch(styles.socket),
+      FileKind::BlockDevice | FileKind::CharDevice => styles.file.patch(styles.device),
+    };
+    if self.exec {
+      res = res.patch(styles.exec);
+    }
+    if self.setuid || self.setgid {
+      res = res.patch(styles.setuid);
+    }
+    if self.link {
+      res = res.patch(styles.link);
+    }
+    if self.broken {
+      res = res.patch(styles.broken_link);
+    }
+    res
+  }
+}
+
+/// A style applied on top of the names of the entries matching a pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleRule {
+  pub pattern: EntryPattern,
+  pub style: Style,
+  /// The style as written, to resolve its color names again
+  pub spec: String,
+}
+
+#[derive(Default)]
+pub struct StyleRules {
+  rules: Vec<StyleRule>,
+}
+
+impl StyleRules {
+  pub fn add(&mut self, rule: StyleRule) {
+    self.rules.push(rule);
+  }
+
+  /// Resolve the styles again, after a color named with `set-color` changed
+  pub fn update_styles(&mut self, config: &Config) {
+    for rule in &mut self.rules {
+      if let Ok(style) = config.parse_style(&rule.spec) {
+        rule.style = style;
+      }
+    }
+  }
+
+  /// `style` patched with the styles of all rules matching the entry, in the
+  /// order they were added. `mime` is only called for rules matching MIME types
+  pub fn apply(&self, style: Style, path: &Path, mime: impl Fn() -> &'static str) -> Style {
+    self
+      .rules
+      .iter()
+      .filter(|r| match &r.pattern {
+        EntryPattern::Glob(_) => r.pattern.matches(path, ""),
+        EntryPattern::Mime(_) => r.pattern.matches(path, mime()),
+      })
+      .fold(style, |style, r| style.patch(r.style))
+  }
+}
+
+lazy_static! {
+  /// The colors of `$LS_COLORS`, read once at startup
+  pub static ref LS_COLORS: LsColors =
+    LsColors::parse(&std::env::var("LS_COLORS").unwrap_or_default());
+}
+
+/// Styles parsed from a `$LS_COLORS` string like `di=01;34:*.tar=01;31`
+#[derive(Debug, Default)]
+pub struct LsColors {
+  /// Styles by entry type, like `di` or `ex`
+  types: Vec<(String, Style)>,
+  /// Patterns like `*.tar` matched against the names of regular files
+  globs: Vec<(String, Style)>,
+  /// Whether symlinks get the style of their target (`ln=target`)
+  link_target: bool,
+}
+
+impl LsColors {
+  pub fn parse(input: &str) -> LsColors {
+    let mut res = LsColors::default();
+    for (key, val) in input.split(':').filter_map(|e| e.split_once('=')) {
+      if key == "ln" && val == "target" {
+        res.link_target = true;
+      } else if key.starts_with('*') {
+        res.globs.push((key.to_string(), parse_sgr(val)));
+      } else {
+        res.types.push((key.to_string(), parse_sgr(val)));
+      }
+    }
+    res
+  }
+
+  fn type_style(&self, key: &str) -> Option<Style> {
+    self
+      .types
+      .iter()
+      .rev()
+      .find(|(k, _)| k == key)
+      .map(|(_, s)| *s)
+  }
+
+  /// The style `ls` would use for an entry, if `$LS_COLORS` has one
+  pub fn style(&self, info: &EntryInfo, path: &Path) -> Option<Style> {
+    let key = if info.broken {
+      "or"
+    } else if info.link && !self.link_target {
+      "ln"
+    } else {
+      match info.kind {
+        FileKind::Dir => "di",
+        FileKind::Fifo => "pi",
+        FileKind::Socket => "so",
+        FileKind::BlockDevice => "bd",
+        FileKind::CharDevice => "cd",
+        FileKind::File if info.setuid => "su",
+        FileKind::File if info.setgid => "sg",
+        FileKind::File if info.exec => "ex",
+        FileKind::File => {
+          // Like `ls`, patterns only apply to regular files
+          let glob = self
+            .globs
+            .iter()
+            .rev()
+            .find(|(pattern, _)| suffix_matches(pattern, path));
+          if let Some((_, style)) = glob {
+            return Some(*style);
+          }
+          "fi"
+        }
+      }
+    };
+    self.type_style(key)
+  }
+}
+
+/// `ls` matches `*suffix` patterns against the end of the file name, ignoring case
+fn suffix_matches(pattern: &str, path: &Path) -> bool {
+  let name = path
+    .file_name()
+    .map(|n| n.to_string_lossy().to_lowercase())
+    .unwrap_or_default();
+  match pattern.strip_prefix('*') {
+    Some(suffix) => name.ends_with(&suffix.to_lowercase()),
+    None => false,
+  }
+}
+
+/// Parse the SGR codes of an `$LS_COLORS` entry, i.e. `01;38;5;208`
+pub fn parse_sgr(codes: &str) -> Style {
+  const BASIC: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Gray,
+  ];
+  const BRIGHT: [Color; 8] = [
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightMagenta,
+    Color::LightCyan,
+    Color::White,
+  ];
+  let codes: Vec<u8> = codes.split(';').filter_map(|c| c.parse().ok()).collect();
+  let mut res = Style::default();
+  let mut iter = codes.into_iter();
+  while let Some(code) = iter.next() {
+    res = match code {
+      0 => Style::reset(),
+      1 => res.add_modifier(Modifier::BOLD),
+      2 => res.add_modifier(Modifier::DIM),
+      3 => res.add_modifier(Modifier::ITALIC),
+      4 => res.add_modifier(Modifier::UNDERLINED),
+      5 => res.add_modifier(Modifier::SLOW_BLINK),
+      7 => res.add_modifier(Modifier::REVERSED),
+      8 => res.add_modifier(Modifier::HIDDEN),
+      9 => res.add_modifier(Modifier::CROSSED_OUT),
+      30..=37 => res.fg(BASIC[code as usize - 30]),
+      40..=47 => res.bg(BASIC[code as usize - 40]),
+      90..=97 => res.fg(BRIGHT[code as usize - 90]),
+      100..=107 => res.bg(BRIGHT[code as usize - 100]),
+      39 => res.fg(Color::Reset),
+      49 => res.bg(Color::Reset),
+      38 | 48 => {
+        let color = match iter.next() {
+          Some(5) => iter.next().map(Color::Indexed),
+          Some(2) => match (iter.next(), iter.next(), iter.next()) {
+            (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+            _ => None,
+          },
+          _ => None,
+        };
+        match (color, code) {
+          (Some(c), 38) => res.fg(c),
+          (Some(c), _) => res.bg(c),
+          (None, _) => res,
+        }
+      }
+      _ => res,
+    };
+  }
+  res
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::style_rules::*;
+
+  #[test]
+  fn ls_colors() {
+    assert_eq!(
+      parse_sgr("01;34"),
+      Style::default()
+        .fg(Color::Blue)
+        .add_modifier(Modifier::BOLD)
+    );
+    assert_eq!(
+      parse_sgr("38;5;208;48;2;1;2;3"),
+      Style::default()
+        .fg(Color::Indexed(208))
+        .bg(Color::Rgb(1, 2, 3))
+    );
+    let colors = LsColors::parse("di=01;34:ex=01;32:su=37;41:sg=30;43:*.tar=01;31:*README=33");
+    let file = EntryInfo {
+      kind: FileKind::File,
+      link: false,
+      broken: false,
+      exec: false,
+      setuid: false,
+      setgid: false,
+    };
+    let red = parse_sgr("01;31");
+    assert_eq!(colors.style(&file, Path::new("a.TAR")), Some(red));
+    assert_eq!(
+      colors.style(&file, Path::new("README")),
+      Some(parse_sgr("33"))
+    );
+    assert_eq!(colors.style(&file, Path::new("a.rs")), None);
+    let exec = EntryInfo { exec: true, ..file };
+    assert_eq!(
+      colors.style(&exec, Path::new("a.tar")),
+      Some(parse_sgr("01;32"))
+    );
+    let setuid = EntryInfo {
+      setuid: true,
+      ..exec
+    };
+    assert_eq!(
+      colors.style(&setuid, Path::new("a")),
+      Some(parse_sgr("37;41"))
+    );
+    let setgid = EntryInfo {
+      setgid: true,
+      ..exec
+    };
+    assert_eq!(
+      colors.style(&setgid, Path::new("a")),
+      Some(parse_sgr("30;43"))
+    );
+    let dir = EntryInfo {
+      kind: FileKind::Dir,
+      ..file
+    };
+    assert_eq!(
+      colors.style(&dir, Path::new("a.tar")),
+      Some(parse_sgr("01;34"))
+    );
+  }
+
+  #[test]
+  fn style_rules_are_patched_in_order() {
+    let mut config = Config::default();
+    config.set_color("accent", "blue").unwrap();
+    let mut rules = StyleRules::default();
+    let add = |rules: &mut StyleRules, pattern: &str, mime: bool, spec: &str| {
+      rules.add(StyleRule {
+        pattern: EntryPattern::new(pattern, mime).unwrap(),
+        style: config.parse_style(spec).unwrap(),
+        spec: spec.to_string(),
+      })
+    };
+    add(&mut rules, "*.rs", false, "red");
+    add(&mut rules, "main.*", false, "+b");
+    add(&mut rules, "image/*", true, "accent");
+    let base = Style::default().fg(Color::Green);
+    let no_mime = || -> &'static str { panic!("mime should not be needed") };
+    assert_eq!(
+      rules.apply(base, Path::new("src/main.rs"), || "text/x-rust"),
+      Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+    );
+    assert_eq!(
+      rules.apply(base, Path::new("a.png"), || "image/png").fg,
+      Some(Color::Blue)
+    );
+    let mut glob_only = StyleRules::default();
+    add(&mut glob_only, "*.rs", false, "");
+    assert_eq!(glob_only.apply(base, Path::new("a.c"), no_mime), base);
+
+    config.set_color("accent", "magenta").unwrap();
+    rules.update_styles(&config);
+    assert_eq!(
+      rules.apply(base, Path::new("a.png"), || "image/png").fg,
+      Some(Color::Magenta)
+    );
+  }
+}
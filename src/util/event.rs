@@ -1,20 +1,123 @@
-use std::io;
-use std::sync::mpsc;
+use std::io::ErrorKind;
+use std::sync::{mpsc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-use termion::event::{Event as TEvent, Key, MouseEvent};
-use termion::input::TermRead;
+use termion::event::{parse_event, Event as TEvent, Key, MouseEvent};
+
+use crate::jobs::JobDone;
 
 pub enum Event<A, B> {
   Key(A),
   Mouse(B),
   Tick,
+  JobDone(JobDone),
+}
+
+/// Set while another process owns the terminal, i.e. during `shell -interactive`.
+/// The input thread holds the lock while it waits for and reads input.
+static INPUT_PAUSED: Mutex<bool> = Mutex::new(false);
+
+/// Stop reading from stdin, so a child process gets all input.
+/// When pausing, this waits until the input thread is done with its current
+/// read, so no input meant for the child is read afterwards.
+pub fn pause_input(paused: bool) {
+  *INPUT_PAUSED.lock().unwrap_or_else(|e| e.into_inner()) = paused;
+}
+
+/// Wait up to `timeout` for stdin to become readable
+fn stdin_ready(timeout: Duration) -> bool {
+  let mut fds = libc::pollfd {
+    fd: libc::STDIN_FILENO,
+    events: libc::POLLIN,
+    revents: 0,
+  };
+  let res = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+  if res < 0 && std::io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+    // Avoid spinning on persistent errors
+    thread::sleep(timeout);
+  }
+  res > 0
+}
+
+/// Parse the events in a chunk of input, returning them with the number of
+/// bytes used. Unless `complete` is set, a sequence cut off at the end of the
+/// chunk is left for the next call, once more input was read.
+/// A lone escape byte at the end of complete input is the escape key
+fn parse_events(bytes: &[u8], complete: bool) -> (Vec<TEvent>, usize) {
+  let mut res = vec![];
+  let mut start = 0;
+  while start < bytes.len() {
+    let mut iter = bytes[start + 1..].iter().map(|b| Ok(*b));
+    let evt = if bytes[start] == b'\x1B' && iter.len() == 0 {
+      Ok(TEvent::Key(Key::Esc))
+    } else {
+      parse_event(bytes[start], &mut iter)
+    };
+    let lone_esc = bytes[start] == b'\x1B' && start + 1 == bytes.len();
+    if !complete && iter.len() == 0 && (evt.is_err() || lone_esc) {
+      break;
+    }
+    if let Ok(evt) = evt {
+      res.push(evt);
+    }
+    start = bytes.len() - iter.len();
+  }
+  (res, start)
+}
+
+/// Read input events from stdin until it is closed
+fn read_input(tx: mpsc::Sender<Event<Key, MouseEvent>>) {
+  let mut buf = [0u8; 256];
+  // Input that was read, but not parsed yet
+  let mut pending: Vec<u8> = vec![];
+  loop {
+    let paused = INPUT_PAUSED.lock().unwrap_or_else(|e| e.into_inner());
+    if *paused {
+      drop(paused);
+      pending.clear();
+      thread::sleep(Duration::from_millis(50));
+      continue;
+    }
+    let ready = stdin_ready(Duration::from_millis(50));
+    let n = if ready {
+      unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) }
+    } else {
+      0
+    };
+    let err = std::io::Error::last_os_error();
+    drop(paused);
+    if ready && n == 0 {
+      return;
+    }
+    if n < 0 {
+      if err.kind() != ErrorKind::Interrupted && err.kind() != ErrorKind::WouldBlock {
+        thread::sleep(Duration::from_millis(50));
+      }
+      continue;
+    }
+    pending.extend_from_slice(&buf[..n as usize]);
+    // Without new input, a cut off sequence is not going to be completed
+    let (events, used) = parse_events(&pending, !ready);
+    pending.drain(..used);
+    for evt in events {
+      let evt = match evt {
+        TEvent::Key(key) => Event::Key(key),
+        TEvent::Mouse(mouse) => Event::Mouse(mouse),
+        _ => continue,
+      };
+      if let Err(err) = tx.send(evt) {
+        eprintln!("{}", err);
+        return;
+      }
+    }
+  }
 }
 
 /// A small event handler that wrap termion input and tick events. Each event
 /// type is handled in its own thread and returned to a common `Receiver`
 pub struct Events {
+  tx: mpsc::Sender<Event<Key, MouseEvent>>,
   rx: mpsc::Receiver<Event<Key, MouseEvent>>,
   _input_handle: thread::JoinHandle<()>,
   _tick_handle: thread::JoinHandle<()>,
@@ -22,14 +125,12 @@ pub struct Events {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Config {
-  pub exit_key: Key,
   pub tick_rate: Duration,
 }
 
 impl Default for Config {
   fn default() -> Config {
     Config {
-      exit_key: Key::Char('q'),
       tick_rate: Duration::from_millis(250),
     }
   }
@@ -44,28 +145,10 @@ impl Events {
     let (tx, rx) = mpsc::channel();
     let _input_handle = {
       let tx = tx.clone();
-      thread::spawn(move || {
-        let stdin = io::stdin();
-        for evt in stdin.events().flatten() {
-          match evt {
-            TEvent::Key(key) => {
-              if let Err(err) = tx.send(Event::Key(key)) {
-                eprintln!("{}", err);
-                return;
-              }
-            }
-            TEvent::Mouse(mouse) => {
-              if let Err(err) = tx.send(Event::Mouse(mouse)) {
-                eprintln!("{}", err);
-                return;
-              }
-            }
-            _ => (),
-          }
-        }
-      })
+      thread::spawn(move || read_input(tx))
     };
     let _tick_handle = {
+      let tx = tx.clone();
       thread::spawn(move || loop {
         if tx.send(Event::Tick).is_err() {
           break;
@@ -74,13 +157,69 @@ impl Events {
       })
     };
     Events {
+      tx,
       rx,
       _input_handle,
       _tick_handle,
     }
   }
 
+  /// A sender to post events from other threads
+  pub fn sender(&self) -> mpsc::Sender<Event<Key, MouseEvent>> {
+    self.tx.clone()
+  }
+
   pub fn next(&self) -> Result<Event<Key, MouseEvent>, mpsc::RecvError> {
     self.rx.recv()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use crate::util::event::parse_events;
+  use termion::event::{Event, Key};
+
+  #[test]
+  fn input_parsing() {
+    assert_eq!(parse_events(b"\x1b", true), (vec![Event::Key(Key::Esc)], 1));
+    assert_eq!(
+      parse_events(b"j\x1bk\x1b[A", true),
+      (
+        vec![
+          Event::Key(Key::Char('j')),
+          Event::Key(Key::Alt('k')),
+          Event::Key(Key::Up)
+        ],
+        6
+      )
+    );
+  }
+
+  #[test]
+  fn cut_off_sequences_are_kept() {
+    assert_eq!(
+      parse_events(b"j\x1b[", false),
+      (vec![Event::Key(Key::Char('j'))], 1)
+    );
+    assert_eq!(parse_events(b"\x1b", false), (vec![], 0));
+    assert_eq!(
+      parse_events("\u{e9}".as_bytes()[..1].as_ref(), false),
+      (vec![], 0)
+    );
+    assert_eq!(
+      parse_events(b"\x1b[A", false),
+      (vec![Event::Key(Key::Up)], 3)
+    );
+    assert_eq!(
+      parse_events(b"\x1b[<0;3;4M", false),
+      (
+        vec![Event::Mouse(termion::event::MouseEvent::Press(
+          termion::event::MouseButton::Left,
+          3,
+          4
+        ))],
+        9
+      )
+    );
+  }
+}
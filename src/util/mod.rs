@@ -1,6 +1,11 @@
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 use tui::widgets::ListState;
 
 pub mod event;
+pub mod signal;
+pub mod term;
 
 pub struct StatefulList<T> {
   pub state: ListState,
@@ -15,7 +20,6 @@ impl<T> StatefulList<T> {
     }
   }
 
-  #[allow(dead_code)]
   pub fn with_items(items: Vec<T>) -> StatefulList<T> {
     StatefulList {
       state: ListState::default(),
@@ -60,3 +64,107 @@ impl<T> StatefulList<T> {
     self.state.select(Some(index));
   }
 }
+
+/// A glob pattern, compiled once when it is set.
+/// Patterns containing a `/` are matched against the whole path, others only
+/// against the file name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Glob(glob::Pattern);
+
+impl Glob {
+  pub fn new(pattern: &str) -> Result<Glob, String> {
+    glob::Pattern::new(pattern)
+      .map(Glob)
+      .map_err(|e| format!("invalid pattern '{}': {}", pattern, e))
+  }
+
+  pub fn as_str(&self) -> &str {
+    self.0.as_str()
+  }
+
+  /// Match the pattern against a plain string, i.e. a MIME type
+  pub fn matches(&self, s: &str) -> bool {
+    self.0.matches(s)
+  }
+
+  pub fn matches_path(&self, path: &Path) -> bool {
+    if self.as_str().contains('/') {
+      self.0.matches_path(path)
+    } else {
+      path
+        .file_name()
+        .map(|n| self.0.matches(&n.to_string_lossy()))
+        .unwrap_or(false)
+    }
+  }
+}
+
+/// Match a glob pattern against a path, see `Glob`.
+/// Invalid patterns never match.
+pub fn glob_matches(pattern: &str, path: &Path) -> bool {
+  Glob::new(pattern)
+    .map(|p| p.matches_path(path))
+    .unwrap_or(false)
+}
+
+/// Create a new directory only accessible by the current user in the
+/// temporary directory, named `prefix` followed by random characters
+pub fn make_temp_dir(prefix: &str) -> std::io::Result<PathBuf> {
+  let template = std::env::temp_dir().join(format!("{}XXXXXX", prefix));
+  let mut bytes = CString::new(template.into_os_string().into_vec())?.into_bytes_with_nul();
+  if unsafe { libc::mkdtemp(bytes.as_mut_ptr().cast()) }.is_null() {
+    return Err(std::io::Error::last_os_error());
+  }
+  bytes.pop();
+  Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Quote a string as a single word for `sh`
+pub fn shell_quote(s: &str) -> String {
+  format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// A temporary directory for tests, removed with its contents on drop
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+  pub fn new(prefix: &str) -> TempDir {
+    TempDir(make_temp_dir(prefix).unwrap())
+  }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+  type Target = Path;
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_dir_all(&self.0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::util::*;
+
+  #[test]
+  fn quote_for_shell() {
+    assert_eq!(shell_quote("a b"), "'a b'");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    let printf = |arg: &str| {
+      let out = std::process::Command::new("sh")
+        .args(["-c", &format!("printf %s {}", shell_quote(arg))])
+        .output()
+        .unwrap();
+      String::from_utf8(out.stdout).unwrap()
+    };
+    assert_eq!(printf("it's $(x) `y`; z"), "it's $(x) `y`; z");
+  }
+}
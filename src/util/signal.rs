@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigusr1(_: libc::c_int) {
+  RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Request a config reload on SIGUSR1, i.e. from `kill -USR1 <pid>`
+pub fn install_reload_handler() {
+  unsafe {
+    libc::signal(
+      libc::SIGUSR1,
+      on_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t,
+    );
+  }
+}
+
+/// Whether a reload was requested since the last call
+pub fn take_reload_request() -> bool {
+  RELOAD_REQUESTED.swap(false, Ordering::SeqCst)
+}
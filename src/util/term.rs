@@ -0,0 +1,85 @@
+use super::event;
+use std::io::Write;
+use std::sync::OnceLock;
+
+static ORIG_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+
+/// Remember the terminal attributes before entering raw mode, so they can be
+/// restored when sidetree panics.
+pub fn save_termios() {
+  let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+  if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut termios) } == 0 {
+    let _ = ORIG_TERMIOS.set(termios);
+  }
+}
+
+const MOUSE_ON: &str = "\x1b[?1000h\x1b[?1002h\x1b[?1015h\x1b[?1006h";
+const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1015l\x1b[?1002l\x1b[?1000l";
+
+/// Leave the alternate screen, disable mouse reporting, show the cursor and
+/// restore the saved terminal attributes.
+pub fn restore() {
+  let mut stdout = std::io::stdout();
+  let _ = write!(
+    stdout,
+    "{}{}{}",
+    MOUSE_OFF,
+    termion::screen::ToMainScreen,
+    termion::cursor::Show
+  );
+  let _ = stdout.flush();
+  if let Some(termios) = ORIG_TERMIOS.get() {
+    unsafe {
+      libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, termios);
+    }
+  }
+}
+
+/// Hand the terminal over to another process, or the shell on `<c-z>`.
+/// Input is no longer read until `resume` is called.
+pub fn suspend() {
+  event::pause_input(true);
+  restore();
+}
+
+/// Take the terminal back after `suspend`.
+/// The caller has to redraw the whole screen afterwards
+pub fn resume() {
+  if let Some(termios) = ORIG_TERMIOS.get() {
+    let mut raw = *termios;
+    unsafe {
+      libc::cfmakeraw(&mut raw);
+      libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &raw);
+    }
+  }
+  let mut stdout = std::io::stdout();
+  let _ = write!(
+    stdout,
+    "{}{}{}",
+    termion::screen::ToAlternateScreen,
+    MOUSE_ON,
+    termion::cursor::Hide
+  );
+  let _ = stdout.flush();
+  event::pause_input(false);
+}
+
+/// Stop the process with SIGTSTP like a shell job, and take the terminal back
+/// when it is continued
+pub fn suspend_process() {
+  suspend();
+  unsafe {
+    libc::raise(libc::SIGTSTP);
+  }
+  resume();
+}
+
+/// Restore the terminal before printing the panic message, so it is readable
+/// and the shell is usable afterwards.
+pub fn install_panic_hook() {
+  let default_hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |info| {
+    restore();
+    default_hook(info);
+  }));
+}